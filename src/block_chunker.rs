@@ -0,0 +1,262 @@
+//! Chunking that follows the structure of a Notion page rather than its flattened text.
+//!
+//! A page is cut into heading sections at its top-level headings, and each section is
+//! kept in one chunk when it fits. Longer sections are split between top-level blocks
+//! only, so toggles, code blocks, tables and equations always stay whole. Every chunk
+//! carries the breadcrumb of headings it sits under.
+
+use std::ops::Range;
+use crate::notion_markdown::blocks_to_markdown_with_spans;
+use crate::notion_pages_setup::BlockNode;
+use crate::notion_types::{plain_text, BlockType};
+use crate::text_chunker::{chunk_text, ChunkingStrategy, TextChunk};
+
+/// A page rendered to Markdown together with the chunks cut from it. The character
+/// offsets of the chunks point into `page_text`.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkedPage{
+    pub page_text: String,
+    pub chunks: Vec<TextChunk>
+}
+
+/// Chunks the top-level blocks of a page, keeping chunks below `max_chars` characters
+/// unless a single unsplittable block is larger than that.
+pub fn chunk_block_tree(nodes: &[BlockNode], max_chars: usize) -> ChunkedPage{
+    let max_chars = max_chars.max(1);
+    let mut chunked_page = ChunkedPage::default();
+
+    for section in heading_sections(nodes){
+        let section_nodes = &nodes[section.start..section.end];
+        // The section is rendered once and sliced into chunks, so numbered lists keep
+        // counting across chunk boundaries.
+        let (section_text, block_spans) = blocks_to_markdown_with_spans(section_nodes);
+
+        if section_text.chars().count() <= max_chars {
+            chunked_page.push_text(&section_text, &section.heading_path, section_nodes, None);
+            continue;
+        }
+
+        let block_char_spans = char_spans(&section_text, &block_spans);
+        let group_char_count = |first: usize, last: usize| block_char_spans[last].end - block_char_spans[first].start;
+
+        // Pack consecutive top-level blocks of the section until `max_chars` is reached.
+        let mut group_start = 0;
+        while group_start < section_nodes.len() {
+            let mut group_end = group_start + 1;
+            // A heading always shares its chunk with the block after it.
+            if heading_level(&section_nodes[group_start].block.block_type).is_some() && group_end < section_nodes.len() {
+                group_end += 1;
+            }
+            while group_end < section_nodes.len() && group_char_count(group_start, group_end) <= max_chars {
+                group_end += 1;
+            }
+
+            let group_nodes = &section_nodes[group_start..group_end];
+            let group_text = &section_text[block_spans[group_start].start..block_spans[group_end - 1].end];
+
+            // A group that is still too long is split on sentence boundaries, unless
+            // splitting it would break a toggle, code block, table or equation.
+            let split_max_chars = match group_char_count(group_start, group_end - 1) > max_chars && !group_nodes.iter().any(is_unsplittable) {
+                true => Some(max_chars),
+                false => None
+            };
+            chunked_page.push_text(group_text, &section.heading_path, group_nodes, split_max_chars);
+
+            group_start = group_end;
+        }
+    }
+
+    return chunked_page;
+}
+
+impl ChunkedPage{
+    /// Appends `rendered_text` to the page text and records it as one chunk, or as
+    /// several sentence-based chunks when `split_max_chars` is set.
+    fn push_text(&mut self, rendered_text: &str, heading_path: &[String], nodes: &[BlockNode], split_max_chars: Option<usize>){
+        if rendered_text.trim().is_empty() {
+            return;
+        }
+
+        if !self.page_text.is_empty() && !self.page_text.ends_with("\n\n") {
+            self.page_text.push('\n');
+        }
+        let base_char_offset = self.page_text.chars().count();
+        self.page_text.push_str(rendered_text);
+
+        let block_ids: Vec<String> = nodes.iter().map(|x| x.block.id.clone()).collect();
+
+        let pieces = match split_max_chars {
+            Some(max_chars) => chunk_text(rendered_text, &ChunkingStrategy::Sentences{ max_chars }),
+            None => {
+                let leading_whitespace = rendered_text.len() - rendered_text.trim_start().len();
+                let char_start = rendered_text[..leading_whitespace].chars().count();
+                let text = rendered_text.trim().to_string();
+                vec![TextChunk{
+                    chunk_index: 0,
+                    char_start,
+                    char_end: char_start + text.chars().count(),
+                    text,
+                    heading_path: Vec::new(),
+                    block_ids: Vec::new()
+                }]
+            }
+        };
+
+        // A lone heading would be an empty point; the breadcrumb already carries it.
+        for piece in pieces.into_iter().filter(|x| !is_heading_only(&x.text)){
+            self.chunks.push(TextChunk{
+                chunk_index: self.chunks.len(),
+                text: piece.text,
+                heading_path: heading_path.to_vec(),
+                char_start: base_char_offset + piece.char_start,
+                char_end: base_char_offset + piece.char_end,
+                block_ids: block_ids.clone()
+            });
+        }
+    }
+}
+
+/// Converts byte ranges into `text`, in increasing order, into character ranges.
+fn char_spans(text: &str, byte_spans: &[Range<usize>]) -> Vec<Range<usize>>{
+    let mut char_spans = Vec::with_capacity(byte_spans.len());
+    let mut byte_offset = 0;
+    let mut char_offset = 0;
+
+    for byte_span in byte_spans{
+        char_offset += text[byte_offset..byte_span.start].chars().count();
+        let char_start = char_offset;
+        char_offset += text[byte_span.clone()].chars().count();
+        char_spans.push(char_start..char_offset);
+        byte_offset = byte_span.end;
+    }
+
+    return char_spans;
+}
+
+/// A run of top-level blocks starting at a heading (or at the top of the page).
+struct HeadingSection{
+    start: usize,
+    end: usize,
+    /// Titles of the enclosing headings, outermost first, ending with the section's own.
+    heading_path: Vec<String>
+}
+
+fn heading_level(block_type: &BlockType) -> Option<usize>{
+    match block_type{
+        BlockType::Heading1{ .. } => Some(1),
+        BlockType::Heading2{ .. } => Some(2),
+        BlockType::Heading3{ .. } => Some(3),
+        _ => None
+    }
+}
+
+/// Splits the top-level blocks at every heading. Headings nested inside toggles or
+/// columns do not start a section of their own.
+fn heading_sections(nodes: &[BlockNode]) -> Vec<HeadingSection>{
+    let mut sections: Vec<HeadingSection> = Vec::new();
+    let mut heading_stack: Vec<(usize, String)> = Vec::new();
+    let mut section_start = 0;
+
+    for (node_idx, node) in nodes.iter().enumerate(){
+        let level = match heading_level(&node.block.block_type) {
+            Some(level) => level,
+            None => continue
+        };
+
+        if node_idx > section_start {
+            sections.push(HeadingSection{
+                start: section_start,
+                end: node_idx,
+                heading_path: heading_stack.iter().map(|x| x.1.clone()).collect()
+            });
+        }
+        section_start = node_idx;
+
+        while heading_stack.last().is_some_and(|x| x.0 >= level) {
+            heading_stack.pop();
+        }
+        let title = node.block.block_type.rich_text().map(plain_text).unwrap_or_default();
+        heading_stack.push((level, title.trim().to_string()));
+    }
+
+    if section_start < nodes.len() {
+        sections.push(HeadingSection{
+            start: section_start,
+            end: nodes.len(),
+            heading_path: heading_stack.iter().map(|x| x.1.clone()).collect()
+        });
+    }
+
+    return sections;
+}
+
+/// Whether `text` consists of nothing but Markdown heading lines.
+fn is_heading_only(text: &str) -> bool{
+    return text.lines().filter(|x| !x.trim().is_empty()).all(|x| {
+        let level = x.chars().take_while(|x| *x == '#').count();
+        return (1..=6).contains(&level) && x[level..].starts_with(' ');
+    });
+}
+
+/// Whether the block (or anything nested in it) must never be split across chunks.
+fn is_unsplittable(node: &BlockNode) -> bool{
+    let is_unsplittable_block = match &node.block.block_type {
+        BlockType::Toggle{ .. } | BlockType::Code{ .. } | BlockType::Table{ .. } | BlockType::Equation{ .. } => true,
+        BlockType::Heading1{ heading_1: x } | BlockType::Heading2{ heading_2: x } | BlockType::Heading3{ heading_3: x } => x.is_toggleable,
+        _ => false
+    };
+    return is_unsplittable_block || node.children.iter().any(is_unsplittable);
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::notion_types::Block;
+
+    fn block(block_id: &str, block_type: &str, text: &str) -> BlockNode{
+        let block_json = serde_json::json!({
+            "id": block_id,
+            "type": block_type,
+            block_type: { "rich_text": [{ "type": "text", "plain_text": text, "text": { "content": text } }] }
+        });
+        let block: Block = serde_json::from_value(block_json).unwrap();
+        return BlockNode{ block, children: Vec::new() };
+    }
+
+    #[test]
+    fn headings_are_never_chunks_of_their_own(){
+        let long_paragraph = "Words that go on. ".repeat(5);
+        let nodes = vec![
+            block("h1", "heading_1", "Intro é"),
+            block("p1", "paragraph", &long_paragraph),
+            block("h2", "heading_2", "Sub"),
+            block("h3", "heading_2", "Other"),
+            block("p2", "paragraph", &long_paragraph)
+        ];
+        let chunked_page = chunk_block_tree(&nodes, 40);
+
+        for chunk in chunked_page.chunks.iter(){
+            assert!(!chunk.text.trim_start().starts_with('#') || chunk.text.contains("Words"), "heading-only chunk {:?}", chunk.text);
+        }
+        assert_eq!(chunked_page.chunks[0].heading_path, vec!["Intro é".to_string()]);
+        assert!(chunked_page.chunks[0].text.starts_with("# Intro é\n"));
+
+        // Offsets still point into the page text.
+        let page_chars: Vec<char> = chunked_page.page_text.chars().collect();
+        for chunk in chunked_page.chunks.iter(){
+            assert_eq!(page_chars[chunk.char_start..chunk.char_end].iter().collect::<String>(), chunk.text);
+        }
+    }
+
+    #[test]
+    fn numbered_lists_keep_counting_across_chunks(){
+        let nodes: Vec<BlockNode> = (1..=6).map(|x| block(&format!("n{}", x), "numbered_list_item", &format!("Step number {} of the recipe.", x))).collect();
+        let chunked_page = chunk_block_tree(&nodes, 70);
+
+        assert!(chunked_page.chunks.len() > 1);
+        let chunk_texts: Vec<&str> = chunked_page.chunks.iter().map(|x| x.text.as_str()).collect();
+        let numbers: Vec<String> = chunk_texts.iter().flat_map(|x| x.lines()).map(|x| x.split('.').next().unwrap().to_string()).collect();
+        assert_eq!(numbers, vec!["1", "2", "3", "4", "5", "6"], "{:?}", chunk_texts);
+        assert!(chunked_page.chunks.iter().all(|x| x.text.chars().count() <= 70));
+    }
+}
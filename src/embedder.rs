@@ -0,0 +1,153 @@
+//! The interface the vector store uses to turn text into vectors.
+//!
+//! `FastEmbedStruct` implements it with a local ONNX model. `HashEmbedder` needs no
+//! model at all and is meant for tests and offline development.
+
+use crate::embedding_cache::EmbeddingCacheError;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmbeddingError{
+    #[error("unknown embedding model {name:?}, expected one of: {}", valid_models.join(", "))]
+    UnknownModel{ name: String, valid_models: Vec<String> },
+
+    #[error("failed to load embedding model {model}: {source}")]
+    ModelLoad{ model: String, source: anyhow::Error },
+
+    #[error("embedding model {model} is not cached in {}, missing: {}", model_dir.display(), missing_files.join(", "))]
+    ModelNotCached{ model: String, model_dir: PathBuf, missing_files: Vec<String> },
+
+    #[error("cached file {} of embedding model {model} is corrupt: {reason}", file.display())]
+    CorruptModelFile{ model: String, file: PathBuf, reason: String },
+
+    #[error("invalid model digest manifest {}: {reason}", path.display())]
+    DigestManifest{ path: PathBuf, reason: String },
+
+    #[error(transparent)]
+    Cache(#[from] EmbeddingCacheError),
+
+    #[error("failed to embed: {0}")]
+    Embedding(anyhow::Error),
+
+    #[error("the embedding model returned no embedding")]
+    MissingEmbedding
+}
+
+/// Turns documents and queries into fixed-size vectors.
+///
+/// Implementations are shared across threads, since embedding runs on blocking worker
+/// threads during ingestion.
+pub trait Embedder: Send + Sync{
+    /// Embeds documents for storage, in batches of `batch_size` when given.
+    fn embed_documents(&self, documents: Vec<String>, batch_size: Option<usize>) -> Result<Vec<Vec<f32>>, EmbeddingError>;
+
+    /// Embeds a search query.
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>, EmbeddingError>;
+
+    /// Length of every vector produced, i.e. the collection's vector size.
+    fn dimension(&self) -> u64;
+
+    /// Identifies the model, so vectors of different models are never mixed.
+    fn model_id(&self) -> &str;
+}
+
+/// A deterministic embedder that hashes every word into one of `dimension` buckets.
+///
+/// Texts sharing words get similar vectors, which is enough to exercise search end to
+/// end without downloading a model. The output is stable across runs and platforms.
+#[derive(Debug, Clone)]
+pub struct HashEmbedder{
+    dimension: usize,
+    model_id: String
+}
+
+impl HashEmbedder{
+    pub fn new(dimension: usize) -> Self{
+        let dimension = dimension.max(1);
+        return HashEmbedder{
+            dimension,
+            model_id: format!("HashEmbedder{}", dimension)
+        };
+    }
+
+    fn embed_text(&self, text: &str) -> Vec<f32>{
+        let mut embedding = vec![0f32; self.dimension];
+
+        for word in text.split(|x: char| !x.is_alphanumeric()).filter(|x| !x.is_empty()){
+            let word_hash = Sha256::digest(word.to_lowercase().as_bytes());
+            let bucket_idx = u64::from_le_bytes(word_hash[..8].try_into().unwrap()) as usize % self.dimension;
+            // The sign spreads words sharing a bucket out instead of piling them up.
+            let sign = if word_hash[8] & 1 == 0 { 1.0 } else { -1.0 };
+            embedding[bucket_idx] += sign;
+        }
+
+        let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            embedding.iter_mut().for_each(|x| *x /= norm);
+        }
+
+        return embedding;
+    }
+}
+
+impl Embedder for HashEmbedder{
+    fn embed_documents(&self, documents: Vec<String>, _batch_size: Option<usize>) -> Result<Vec<Vec<f32>>, EmbeddingError>{
+        return Ok(documents.iter().map(|x| self.embed_text(x)).collect());
+    }
+
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>, EmbeddingError>{
+        return Ok(self.embed_text(query));
+    }
+
+    fn dimension(&self) -> u64{
+        return self.dimension as u64;
+    }
+
+    fn model_id(&self) -> &str{
+        return &self.model_id;
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn dot(a: &[f32], b: &[f32]) -> f32{
+        return a.iter().zip(b).map(|(x, y)| x * y).sum();
+    }
+
+    #[test]
+    fn hash_embedder_is_deterministic(){
+        let documents = vec!["Boil the pasta in salted water".to_string(), "Whisk eggs with pecorino".to_string()];
+
+        let first_embeddings = HashEmbedder::new(64).embed_documents(documents.clone(), None).unwrap();
+        let second_embeddings = HashEmbedder::new(64).embed_documents(documents, Some(1)).unwrap();
+
+        assert_eq!(first_embeddings, second_embeddings);
+        assert_eq!(HashEmbedder::new(64).embed_query("Boil the pasta in salted water").unwrap(), first_embeddings[0]);
+    }
+
+    #[test]
+    fn hash_embedder_has_the_requested_dimension(){
+        let hash_embedder = HashEmbedder::new(32);
+        let embeddings = hash_embedder.embed_documents(vec!["one".to_string(), "".to_string()], None).unwrap();
+
+        assert_eq!(hash_embedder.dimension(), 32);
+        assert_eq!(hash_embedder.model_id(), "HashEmbedder32");
+        assert!(embeddings.iter().all(|x| x.len() == 32));
+        assert!((dot(&embeddings[0], &embeddings[0]) - 1.0).abs() < 1e-5);
+        assert!(embeddings[1].iter().all(|x| *x == 0.0));
+
+        assert_eq!(HashEmbedder::new(0).dimension(), 1);
+    }
+
+    #[test]
+    fn hash_embedder_ranks_shared_words_higher(){
+        let hash_embedder = HashEmbedder::new(256);
+        let query = hash_embedder.embed_query("pasta carbonara").unwrap();
+        let embeddings = hash_embedder.embed_documents(vec!["Pasta carbonara recipe".to_string(), "Chocolate cake".to_string()], None).unwrap();
+
+        assert!(dot(&query, &embeddings[0]) > dot(&query, &embeddings[1]));
+    }
+}
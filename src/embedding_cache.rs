@@ -0,0 +1,278 @@
+//! Local, file-backed cache of embeddings, keyed by model and the hash of the embedded
+//! text.
+//!
+//! The cache file is JSON Lines, one embedding per line, and is only ever appended to,
+//! so saving a few new vectors never rewrites the ones already cached. Re-running the
+//! ingestion then only sends text that was never embedded before to the model.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Where embeddings are cached when no other path is configured.
+pub const DEFAULT_EMBEDDING_CACHE_PATH: &str = "notion_embedding_cache.jsonl";
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmbeddingCacheError{
+    #[error("failed to read or write embedding cache {path:?}: {source}")]
+    Io{ path: PathBuf, source: std::io::Error },
+
+    #[error("failed to encode embedding cache entry for {path:?}: {source}")]
+    Encode{ path: PathBuf, source: serde_json::Error }
+}
+
+/// How often lookups were answered from the cache since it was opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EmbeddingCacheStats{
+    pub hits: u64,
+    pub misses: u64,
+    /// Number of vectors held by the cache, across all models.
+    pub entries: usize
+}
+
+impl EmbeddingCacheStats{
+    pub fn hit_rate(&self) -> f64{
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        return self.hits as f64 / lookups as f64;
+    }
+}
+
+impl fmt::Display for EmbeddingCacheStats{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        return write!(f, "{} hits, {} misses ({:.1}% hit rate), {} cached embeddings", self.hits, self.misses, self.hit_rate() * 100.0, self.entries);
+    }
+}
+
+/// A single line of the cache file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct CacheEntry{
+    model: String,
+    text_hash: String,
+    embedding: Vec<f32>
+}
+
+/// Hex SHA-256 of `text`, the part of the cache key identifying the embedded text.
+pub fn text_hash(text: &str) -> String{
+    return Sha256::digest(text.as_bytes()).iter().map(|x| format!("{:02x}", x)).collect();
+}
+
+/// Persistent embedding cache, loaded fully into memory. New embeddings are appended to
+/// the file as they are inserted.
+#[derive(Debug)]
+pub struct EmbeddingCache{
+    cache_path: PathBuf,
+    /// (model, text hash) -> embedding.
+    entries: HashMap<(String, String), Vec<f32>>,
+    /// Whether the file ends in a line cut short, which must be terminated before appending.
+    ends_mid_line: bool,
+    hits: u64,
+    misses: u64
+}
+
+impl EmbeddingCache{
+    /// Opens the cache at `cache_path`, starting empty if the file does not exist yet.
+    /// Lines that cannot be decoded, such as one cut short by an interrupted write, are
+    /// skipped.
+    pub fn open(cache_path: impl Into<PathBuf>) -> Result<Self, EmbeddingCacheError>{
+        let cache_path: PathBuf = cache_path.into();
+        let mut entries = HashMap::new();
+
+        if !cache_path.exists() {
+            log::info!("No embedding cache found at {:?}, starting from scratch", cache_path);
+            return Ok(EmbeddingCache{ cache_path, entries, ends_mid_line: false, hits: 0, misses: 0 });
+        }
+
+        let cache_json = std::fs::read_to_string(&cache_path).map_err(|source| EmbeddingCacheError::Io{ path: cache_path.clone(), source })?;
+        let ends_mid_line = !cache_json.is_empty() && !cache_json.ends_with('\n');
+
+        for (line_idx, line) in cache_json.lines().enumerate(){
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str::<CacheEntry>(line) {
+                Ok(entry) => {
+                    entries.insert((entry.model, entry.text_hash), entry.embedding);
+                }
+                Err(err) => {
+                    log::warn!("Skipping line {} of embedding cache {:?}: {}", line_idx + 1, cache_path, err);
+                }
+            }
+        }
+        log::info!("Loaded {} cached embeddings from {:?}", entries.len(), cache_path);
+
+        return Ok(EmbeddingCache{ cache_path, entries, ends_mid_line, hits: 0, misses: 0 });
+    }
+
+    pub fn path(&self) -> &Path{
+        return &self.cache_path;
+    }
+
+    /// The cached embedding of `text` by `model`, counted as a hit or a miss.
+    pub fn get(&mut self, model: &str, text: &str) -> Option<Vec<f32>>{
+        let embedding = self.entries.get(&(model.to_string(), text_hash(text))).cloned();
+        match embedding {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1
+        }
+        return embedding;
+    }
+
+    /// Caches the embeddings of `texts` by `model` and appends the ones not cached yet to
+    /// the cache file. They are only served from the cache once the file write succeeded.
+    /// Embeddings with NaN or infinite components are not cached, since JSON cannot hold
+    /// them.
+    pub fn insert_all(&mut self, model: &str, texts: &[String], embeddings: &[Vec<f32>]) -> Result<(), EmbeddingCacheError>{
+        let mut new_keys = HashSet::new();
+        let mut new_entries = Vec::new();
+        for (text, embedding) in texts.iter().zip(embeddings.iter()){
+            let cache_key = (model.to_string(), text_hash(text));
+            if self.entries.contains_key(&cache_key) || new_keys.contains(&cache_key) {
+                continue;
+            }
+            if !embedding.iter().all(|x| x.is_finite()) {
+                log::warn!("Not caching the embedding of text {} by model {}: it has non-finite components", cache_key.1, model);
+                continue;
+            }
+            new_entries.push(CacheEntry{ model: cache_key.0.clone(), text_hash: cache_key.1.clone(), embedding: embedding.clone() });
+            new_keys.insert(cache_key);
+        }
+
+        if new_entries.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(err) = self.append_entries(&new_entries) {
+            // Part of a line may have reached the file; the next append starts a new one.
+            self.ends_mid_line = true;
+            return Err(err);
+        }
+        self.ends_mid_line = false;
+
+        for entry in new_entries{
+            self.entries.insert((entry.model, entry.text_hash), entry.embedding);
+        }
+
+        return Ok(());
+    }
+
+    fn append_entries(&self, new_entries: &[CacheEntry]) -> Result<(), EmbeddingCacheError>{
+        let cache_file = OpenOptions::new().create(true).append(true).open(&self.cache_path)
+            .map_err(|source| EmbeddingCacheError::Io{ path: self.cache_path.clone(), source })?;
+        let mut cache_writer = BufWriter::new(cache_file);
+        if self.ends_mid_line {
+            writeln!(cache_writer).map_err(|source| EmbeddingCacheError::Io{ path: self.cache_path.clone(), source })?;
+        }
+        for entry in new_entries.iter(){
+            let entry_json = serde_json::to_string(entry).map_err(|source| EmbeddingCacheError::Encode{ path: self.cache_path.clone(), source })?;
+            writeln!(cache_writer, "{}", entry_json).map_err(|source| EmbeddingCacheError::Io{ path: self.cache_path.clone(), source })?;
+        }
+        cache_writer.flush().map_err(|source| EmbeddingCacheError::Io{ path: self.cache_path.clone(), source })?;
+
+        return Ok(());
+    }
+
+    pub fn stats(&self) -> EmbeddingCacheStats{
+        return EmbeddingCacheStats{
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn temp_cache_path(test_name: &str) -> PathBuf{
+        let cache_dir = std::env::temp_dir().join(format!("notion-llm-{}-{}", test_name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        return cache_dir.join("embedding_cache.jsonl");
+    }
+
+    fn texts(texts: &[&str]) -> Vec<String>{
+        return texts.iter().map(|x| x.to_string()).collect();
+    }
+
+    #[test]
+    fn embeddings_survive_a_reopen_and_are_counted(){
+        let cache_path = temp_cache_path("embedding-cache-reopen");
+        let mut embedding_cache = EmbeddingCache::open(&cache_path).unwrap();
+        assert_eq!(embedding_cache.stats(), EmbeddingCacheStats::default());
+
+        assert_eq!(embedding_cache.get("model-a", "pasta"), None);
+        embedding_cache.insert_all("model-a", &texts(&["pasta", "cake", "pasta"]), &[vec![1.0, 2.0], vec![3.0, 4.0], vec![1.0, 2.0]]).unwrap();
+        embedding_cache.insert_all("model-b", &texts(&["pasta"]), &[vec![5.0]]).unwrap();
+        assert_eq!(embedding_cache.get("model-a", "pasta"), Some(vec![1.0, 2.0]));
+        assert_eq!(embedding_cache.stats(), EmbeddingCacheStats{ hits: 1, misses: 1, entries: 3 });
+
+        // Duplicates within a batch are written once.
+        assert_eq!(std::fs::read_to_string(&cache_path).unwrap().lines().count(), 3);
+
+        let mut reopened_cache = EmbeddingCache::open(&cache_path).unwrap();
+        assert_eq!(reopened_cache.stats(), EmbeddingCacheStats{ hits: 0, misses: 0, entries: 3 });
+        assert_eq!(reopened_cache.get("model-a", "cake"), Some(vec![3.0, 4.0]));
+        assert_eq!(reopened_cache.get("model-b", "pasta"), Some(vec![5.0]));
+        assert_eq!(reopened_cache.get("model-b", "cake"), None);
+        assert_eq!(reopened_cache.stats().hit_rate(), 2.0 / 3.0);
+
+        std::fs::remove_dir_all(cache_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn a_truncated_last_line_is_skipped_and_terminated(){
+        let cache_path = temp_cache_path("embedding-cache-truncated");
+        let mut embedding_cache = EmbeddingCache::open(&cache_path).unwrap();
+        embedding_cache.insert_all("model-a", &texts(&["pasta"]), &[vec![1.0]]).unwrap();
+
+        let mut cache_json = std::fs::read_to_string(&cache_path).unwrap();
+        cache_json.push_str(r#"{"model":"model-a","text_hash":"ab"#);
+        std::fs::write(&cache_path, cache_json).unwrap();
+
+        let mut reopened_cache = EmbeddingCache::open(&cache_path).unwrap();
+        assert_eq!(reopened_cache.stats().entries, 1);
+        reopened_cache.insert_all("model-a", &texts(&["cake"]), &[vec![2.0]]).unwrap();
+
+        let mut recovered_cache = EmbeddingCache::open(&cache_path).unwrap();
+        assert_eq!(recovered_cache.stats().entries, 2);
+        assert_eq!(recovered_cache.get("model-a", "pasta"), Some(vec![1.0]));
+        assert_eq!(recovered_cache.get("model-a", "cake"), Some(vec![2.0]));
+
+        std::fs::remove_dir_all(cache_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn non_finite_embeddings_are_not_cached(){
+        let cache_path = temp_cache_path("embedding-cache-non-finite");
+        let mut embedding_cache = EmbeddingCache::open(&cache_path).unwrap();
+        embedding_cache.insert_all("model-a", &texts(&["nan", "inf", "fine"]), &[vec![f32::NAN], vec![1.0, f32::INFINITY], vec![1.0]]).unwrap();
+
+        assert_eq!(embedding_cache.get("model-a", "nan"), None);
+        assert_eq!(embedding_cache.get("model-a", "inf"), None);
+        assert_eq!(EmbeddingCache::open(&cache_path).unwrap().stats().entries, 1);
+
+        std::fs::remove_dir_all(cache_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn embeddings_are_not_served_when_writing_them_failed(){
+        let cache_path = temp_cache_path("embedding-cache-write-failure");
+        let missing_dir_path = cache_path.with_file_name("missing").join("embedding_cache.jsonl");
+        let mut embedding_cache = EmbeddingCache::open(&missing_dir_path).unwrap();
+
+        assert!(matches!(embedding_cache.insert_all("model-a", &texts(&["pasta"]), &[vec![1.0]]), Err(EmbeddingCacheError::Io{ .. })));
+        assert_eq!(embedding_cache.get("model-a", "pasta"), None);
+        assert_eq!(embedding_cache.stats().entries, 0);
+
+        std::fs::remove_dir_all(cache_path.parent().unwrap()).unwrap();
+    }
+}
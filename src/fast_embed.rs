@@ -1,89 +1,370 @@
 use fastembed::{FlagEmbedding, InitOptions, EmbeddingModel, EmbeddingBase};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use crate::embedder::{Embedder, EmbeddingError};
+use crate::embedding_cache::{EmbeddingCache, EmbeddingCacheStats};
 
-pub struct FastEmbedStruct{
-    embeddings_model: FlagEmbedding,
-    pub current_model_name: String,
-    available_model_info: HashMap<String, HashMap<String, String>>
+/// Number of documents embedded per batch when no batch size is given.
+pub const DEFAULT_EMBEDDING_BATCH_SIZE: usize = 256;
+
+/// Directory models are downloaded to and loaded from, the same as fastembed's default.
+pub const DEFAULT_MODEL_CACHE_DIR: &str = "local_cache";
+
+/// Number of tokens a document is truncated to when no maximum is given.
+pub const DEFAULT_MAX_SEQUENCE_LENGTH: usize = 512;
+
+/// Digest manifest `verify_cached_model` reads from, and `pin_cached_model` writes to, a
+/// model's directory when no other manifest is given.
+pub const MODEL_DIGEST_MANIFEST: &str = "SHA256SUMS";
+
+/// Files fastembed loads from the directory of a cached model.
+pub const MODEL_FILES: [&str; 5] = ["model_optimized.onnx", "tokenizer.json", "tokenizer_config.json", "special_tokens_map.json", "config.json"];
+
+/// JSON fields fastembed reads from the files of a cached model, and panics without. An
+/// empty field name only requires the file to be valid JSON.
+const REQUIRED_MODEL_FIELDS: [(&str, &str); 4] = [
+    ("config.json", "pad_token_id"),
+    ("tokenizer_config.json", "model_max_length"),
+    ("tokenizer_config.json", "pad_token"),
+    ("special_tokens_map.json", "")
+];
+
+/// The fastembed models that can be selected by name, e.g. `"BGESmallENV15"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FastEmbedModel{
+    AllMiniLML6V2,
+    #[default]
+    BGEBaseEN,
+    BGEBaseENV15,
+    BGESmallEN,
+    BGESmallENV15,
+    BGESmallZH,
+    MLE5Large
 }
 
-fn enum_to_string(model_enum: EmbeddingModel) -> String{
-    match model_enum{
-        EmbeddingModel::AllMiniLML6V2 => String::from("AllMiniLML6V2"),
-        EmbeddingModel::BGEBaseEN => String::from("BGEBaseEN"),
-        EmbeddingModel::BGEBaseENV15 => String::from("BGEBaseENV15"),
-        EmbeddingModel::BGESmallEN => String::from("BGESmallEN"),
-        EmbeddingModel::BGESmallENV15 => String::from("BGESmallENV15"),
-        EmbeddingModel::BGESmallZH => String::from("BGESmallZH"),
-        EmbeddingModel::MLE5Large => String::from("MLE5Large")
+impl FastEmbedModel{
+    pub const ALL: [FastEmbedModel; 7] = [
+        FastEmbedModel::AllMiniLML6V2,
+        FastEmbedModel::BGEBaseEN,
+        FastEmbedModel::BGEBaseENV15,
+        FastEmbedModel::BGESmallEN,
+        FastEmbedModel::BGESmallENV15,
+        FastEmbedModel::BGESmallZH,
+        FastEmbedModel::MLE5Large
+    ];
+
+    pub fn name(&self) -> &'static str{
+        match self{
+            FastEmbedModel::AllMiniLML6V2 => "AllMiniLML6V2",
+            FastEmbedModel::BGEBaseEN => "BGEBaseEN",
+            FastEmbedModel::BGEBaseENV15 => "BGEBaseENV15",
+            FastEmbedModel::BGESmallEN => "BGESmallEN",
+            FastEmbedModel::BGESmallENV15 => "BGESmallENV15",
+            FastEmbedModel::BGESmallZH => "BGESmallZH",
+            FastEmbedModel::MLE5Large => "MLE5Large"
+        }
+    }
+
+    pub fn valid_names() -> Vec<String>{
+        return Self::ALL.iter().map(|x| x.to_string()).collect();
+    }
+
+    pub fn to_fastembed(&self) -> EmbeddingModel{
+        match self{
+            FastEmbedModel::AllMiniLML6V2 => EmbeddingModel::AllMiniLML6V2,
+            FastEmbedModel::BGEBaseEN => EmbeddingModel::BGEBaseEN,
+            FastEmbedModel::BGEBaseENV15 => EmbeddingModel::BGEBaseENV15,
+            FastEmbedModel::BGESmallEN => EmbeddingModel::BGESmallEN,
+            FastEmbedModel::BGESmallENV15 => EmbeddingModel::BGESmallENV15,
+            FastEmbedModel::BGESmallZH => EmbeddingModel::BGESmallZH,
+            FastEmbedModel::MLE5Large => EmbeddingModel::MLE5Large
+        }
+    }
+
+    /// Directory of the model inside a cache directory, named after fastembed's model code.
+    pub fn model_dir(&self, cache_dir: &Path) -> PathBuf{
+        return cache_dir.join(self.to_fastembed().to_string());
+    }
+
+    /// Text prepended to search queries so they match how the model was trained.
+    fn query_prefix(&self) -> &'static str{
+        match self{
+            FastEmbedModel::BGEBaseEN | FastEmbedModel::BGEBaseENV15 | FastEmbedModel::BGESmallEN | FastEmbedModel::BGESmallENV15 => BGE_EN_QUERY_INSTRUCTION,
+            FastEmbedModel::BGESmallZH => BGE_ZH_QUERY_INSTRUCTION,
+            FastEmbedModel::MLE5Large => "query: ",
+            FastEmbedModel::AllMiniLML6V2 => ""
+        }
+    }
+
+    /// Text prepended to documents; only E5 models mark passages, BGE embeds them as is.
+    fn passage_prefix(&self) -> &'static str{
+        match self{
+            FastEmbedModel::MLE5Large => "passage: ",
+            _ => ""
+        }
     }
 }
 
+impl From<&EmbeddingModel> for FastEmbedModel{
+    fn from(model_enum: &EmbeddingModel) -> Self{
+        match model_enum{
+            EmbeddingModel::AllMiniLML6V2 => FastEmbedModel::AllMiniLML6V2,
+            EmbeddingModel::BGEBaseEN => FastEmbedModel::BGEBaseEN,
+            EmbeddingModel::BGEBaseENV15 => FastEmbedModel::BGEBaseENV15,
+            EmbeddingModel::BGESmallEN => FastEmbedModel::BGESmallEN,
+            EmbeddingModel::BGESmallENV15 => FastEmbedModel::BGESmallENV15,
+            EmbeddingModel::BGESmallZH => FastEmbedModel::BGESmallZH,
+            EmbeddingModel::MLE5Large => FastEmbedModel::MLE5Large
+        }
+    }
+}
 
-impl FastEmbedStruct{
-    pub fn new(embeddings_model: Option<&str>) -> Self{
-        let current_model_name: String;
-
-        let model_name = match embeddings_model {
-            Some("AllMiniLML6V2") => {
-                current_model_name = "AllMiniLML6V2".to_string();
-                EmbeddingModel::AllMiniLML6V2
-            },
-            Some("BGEBaseEN") => {
-                current_model_name = "BGEBaseEN".to_string();
-                EmbeddingModel::BGEBaseEN
-            },
-            Some("BGEBaseENV15") => {
-                current_model_name = "BGEBaseENV15".to_string();
-                EmbeddingModel::BGEBaseENV15
-            },
-            Some("BGESmallEN") => {
-                current_model_name = "BGESmallEN".to_string();
-                EmbeddingModel::BGESmallEN
-            },
-            Some("BGESmallENV15") => {
-                current_model_name = "BGESmallENV15".to_string();
-                EmbeddingModel::BGESmallENV15
-            },
-            Some("BGESmallZH") => {
-                current_model_name = "BGESmallZH".to_string();
-                EmbeddingModel::BGESmallZH
-            },
-            Some("MLE5Large") => {
-                current_model_name = "MLE5Large".to_string();
-                EmbeddingModel::MLE5Large
-            },
-            _ => {
-                current_model_name = "BGEBaseEN".to_string();
-                EmbeddingModel::BGEBaseEN
+impl fmt::Display for FastEmbedModel{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        return f.write_str(self.name());
+    }
+}
+
+impl FromStr for FastEmbedModel{
+    type Err = EmbeddingError;
+
+    /// Accepts the names shown by `Display` as well as fastembed's own model codes
+    /// (e.g. `fast-bge-small-en-v1.5`), ignoring case.
+    fn from_str(model_name: &str) -> Result<Self, Self::Err>{
+        let model_name = model_name.trim();
+
+        let found_model = Self::ALL.into_iter().find(|x| {
+            return x.name().eq_ignore_ascii_case(model_name) || x.to_fastembed().to_string().eq_ignore_ascii_case(model_name);
+        });
+
+        return found_model.ok_or_else(|| EmbeddingError::UnknownModel{
+            name: model_name.to_string(),
+            valid_models: Self::valid_names()
+        });
+    }
+}
+
+/// Instruction BGE models were trained to expect in front of search queries.
+const BGE_EN_QUERY_INSTRUCTION: &str = "Represent this sentence for searching relevant passages: ";
+const BGE_ZH_QUERY_INSTRUCTION: &str = "为这个句子生成表示以用于检索相关文章：";
+
+/// Configures where `FastEmbedStruct` finds its model and how long documents may be.
+#[derive(Debug, Clone)]
+pub struct FastEmbedStructBuilder{
+    model: FastEmbedModel,
+    cache_dir: PathBuf,
+    max_length: usize,
+    offline: bool,
+    embedding_cache_path: Option<PathBuf>
+}
+
+impl Default for FastEmbedStructBuilder{
+    fn default() -> Self{
+        return FastEmbedStructBuilder{
+            model: FastEmbedModel::default(),
+            cache_dir: PathBuf::from(DEFAULT_MODEL_CACHE_DIR),
+            max_length: DEFAULT_MAX_SEQUENCE_LENGTH,
+            offline: false,
+            embedding_cache_path: None
+        };
+    }
+}
+
+impl FastEmbedStructBuilder{
+    pub fn model(mut self, model: FastEmbedModel) -> Self{
+        self.model = model;
+        return self;
+    }
+
+    /// Directory holding one sub-directory per model, as laid out by fastembed.
+    pub fn cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self{
+        self.cache_dir = cache_dir.into();
+        return self;
+    }
+
+    /// Number of tokens documents are truncated to. Models cap it at their own limit.
+    pub fn max_length(mut self, max_length: usize) -> Self{
+        self.max_length = max_length.max(1);
+        return self;
+    }
+
+    /// Never downloads the model. It is loaded from the cache directory only, and
+    /// loading fails with `ModelNotCached` when any of its files is absent.
+    pub fn offline(mut self, offline: bool) -> Self{
+        self.offline = offline;
+        return self;
+    }
+
+    /// Caches embeddings in the file at `embedding_cache_path`, so text embedded before,
+    /// in this run or an earlier one, is not sent to the model again.
+    pub fn embedding_cache(mut self, embedding_cache_path: impl Into<PathBuf>) -> Self{
+        self.embedding_cache_path = Some(embedding_cache_path.into());
+        return self;
+    }
+
+    pub fn build(self) -> Result<FastEmbedStruct, EmbeddingError>{
+        let model_dir = self.model.model_dir(&self.cache_dir);
+
+        // fastembed only downloads when the model directory is missing, so a partially
+        // copied directory would otherwise surface as an obscure I/O error.
+        if self.offline || model_dir.exists() {
+            let missing_files = missing_model_files(&model_dir);
+            if !missing_files.is_empty() {
+                return Err(EmbeddingError::ModelNotCached{ model: self.model.to_string(), model_dir, missing_files });
             }
+            check_model_fields(self.model, &model_dir)?;
+        }
+        log::info!("Loading embedding model {} from {:?}", self.model, model_dir);
+
+        let embedding_cache = match &self.embedding_cache_path {
+            Some(embedding_cache_path) => Some(Mutex::new(EmbeddingCache::open(embedding_cache_path)?)),
+            None => None
         };
 
         let model: FlagEmbedding = FlagEmbedding::try_new(InitOptions {
-            model_name: model_name,
+            model_name: self.model.to_fastembed(),
+            max_length: self.max_length,
+            cache_dir: self.cache_dir.clone(),
             show_download_message: true,
             ..Default::default()
-        }).unwrap();
+        }).map_err(|source| EmbeddingError::ModelLoad{ model: self.model.to_string(), source })?;
+
+        let model_info = FlagEmbedding::list_supported_models().into_iter().find(|x| FastEmbedModel::from(&x.model) == self.model);
+        let current_model_size = match model_info {
+            Some(model_info) => model_info.dim as u64,
+            None => return Err(EmbeddingError::UnknownModel{ name: self.model.to_string(), valid_models: FastEmbedModel::valid_names() })
+        };
 
-        let model_info_store_vec: HashMap<String, HashMap<String, String>> = FlagEmbedding::list_supported_models().iter().map(|x| {
-            let mut inner_map = HashMap::new();
-            let model_string = enum_to_string(x.model.clone());
-            inner_map.insert("model_size".to_string(), x.dim.to_string());
-            inner_map.insert("model_description".to_string(), x.description.clone());
-            (model_string, inner_map)
-        }).collect();
+        // Truncating at another length changes the vectors, so a non-default length is
+        // part of the model ID recorded with every indexed page and cached embedding.
+        let model_id = match self.max_length == DEFAULT_MAX_SEQUENCE_LENGTH {
+            true => self.model.to_string(),
+            false => format!("{}:{}", self.model, self.max_length)
+        };
 
-        return FastEmbedStruct{
+        return Ok(FastEmbedStruct{
             embeddings_model: model,
-            current_model_name: current_model_name,
-            available_model_info: model_info_store_vec
+            current_model: self.model,
+            current_model_name: self.model.to_string(),
+            model_id,
+            current_model_size,
+            cache_dir: self.cache_dir,
+            max_length: self.max_length,
+            embedding_cache
+        });
+    }
+}
+
+pub struct FastEmbedStruct{
+    embeddings_model: FlagEmbedding,
+    current_model: FastEmbedModel,
+    pub current_model_name: String,
+    model_id: String,
+    current_model_size: u64,
+    cache_dir: PathBuf,
+    max_length: usize,
+    embedding_cache: Option<Mutex<EmbeddingCache>>
+}
+
+impl FastEmbedStruct{
+    /// Loads `embeddings_model` by name, or `BGEBaseEN` when `None`. Unknown names are
+    /// rejected with the list of valid ones.
+    pub fn new(embeddings_model: Option<&str>) -> Result<Self, EmbeddingError>{
+        let current_model = match embeddings_model {
+            Some(model_name) => model_name.parse()?,
+            None => FastEmbedModel::default()
+        };
+        return Self::from_model(current_model);
+    }
+
+    pub fn from_model(current_model: FastEmbedModel) -> Result<Self, EmbeddingError>{
+        return Self::builder().model(current_model).build();
+    }
+
+    pub fn builder() -> FastEmbedStructBuilder{
+        return FastEmbedStructBuilder::default();
+    }
+
+    pub fn cache_dir(&self) -> &Path{
+        return &self.cache_dir;
+    }
+
+    pub fn max_length(&self) -> usize{
+        return self.max_length;
+    }
+
+    pub fn current_model(&self) -> FastEmbedModel{
+        return self.current_model;
+    }
+
+    /// Hit and miss counts of the embedding cache, if one is configured.
+    pub fn embedding_cache_stats(&self) -> Option<EmbeddingCacheStats>{
+        let embedding_cache = self.embedding_cache.as_ref()?;
+        return Some(embedding_cache.lock().unwrap_or_else(|x| x.into_inner()).stats());
+    }
+
+    /// Embeds `stuff_to_embed` in batches of `batch_size` documents (by default
+    /// `DEFAULT_EMBEDDING_BATCH_SIZE`), returning one vector per document in order.
+    /// With an embedding cache, only the documents missing from it reach the model.
+    pub fn embed_stuff(&self, stuff_to_embed: Vec<String>, batch_size: Option<usize>) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        let embedding_cache = match &self.embedding_cache {
+            Some(embedding_cache) => embedding_cache,
+            None => return self.embed_with_model(stuff_to_embed, batch_size)
         };
+
+        let mut embeddings: Vec<Option<Vec<f32>>> = {
+            let mut embedding_cache = embedding_cache.lock().unwrap_or_else(|x| x.into_inner());
+            stuff_to_embed.iter().map(|x| embedding_cache.get(&self.model_id, x)).collect()
+        };
+        let missed_idxs: Vec<usize> = embeddings.iter().enumerate().filter(|x| x.1.is_none()).map(|x| x.0).collect();
+        log::debug!("Embedding cache answered {} of {} documents", stuff_to_embed.len() - missed_idxs.len(), stuff_to_embed.len());
+
+        if !missed_idxs.is_empty() {
+            let missed_texts: Vec<String> = missed_idxs.iter().map(|&x| stuff_to_embed[x].clone()).collect();
+            let missed_embeddings = self.embed_with_model(missed_texts.clone(), batch_size)?;
+
+            // The vectors are valid either way; failing to cache them only costs a re-embed.
+            let mut embedding_cache = embedding_cache.lock().unwrap_or_else(|x| x.into_inner());
+            if let Err(err) = embedding_cache.insert_all(&self.model_id, &missed_texts, &missed_embeddings) {
+                log::warn!("Failed to cache {} embeddings: {}", missed_texts.len(), err);
+            }
+
+            for (missed_idx, embedding) in missed_idxs.into_iter().zip(missed_embeddings){
+                embeddings[missed_idx] = Some(embedding);
+            }
+        }
+
+        return embeddings.into_iter().map(|x| x.ok_or(EmbeddingError::MissingEmbedding)).collect();
     }
 
+    fn embed_with_model(&self, stuff_to_embed: Vec<String>, batch_size: Option<usize>) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        let batch_size = batch_size.unwrap_or(DEFAULT_EMBEDDING_BATCH_SIZE).max(1);
+        log::debug!("Embedding {} documents in batches of {}", stuff_to_embed.len(), batch_size);
 
-    pub fn embed_stuff(&self, stuff_to_embed: Vec<String>) -> Vec<Vec<f32>> {
-        let embeddings = self.embeddings_model.embed(stuff_to_embed, None).unwrap();
-        return embeddings;
+        let embeddings = self.embeddings_model.embed(stuff_to_embed, Some(batch_size)).map_err(EmbeddingError::Embedding)?;
+        return Ok(embeddings);
+    }
+
+    /// Embeds documents for storage, with the passage prefix of the current model.
+    pub fn embed_passages(&self, passages: Vec<String>, batch_size: Option<usize>) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        let prefix = self.current_model.passage_prefix();
+        if prefix.is_empty() {
+            return self.embed_stuff(passages, batch_size);
+        }
+        return self.embed_stuff(passages.into_iter().map(|x| format!("{}{}", prefix, x)).collect(), batch_size);
+    }
+
+    /// Embeds a search query, with the query prefix or instruction of the current model.
+    /// Queries bypass the embedding cache, so they neither fill it nor count in its stats.
+    pub fn embed_query(&self, query: &str) -> Result<Vec<f32>, EmbeddingError> {
+        let prefixed_query = format!("{}{}", self.current_model.query_prefix(), query);
+        let mut embeddings = self.embed_with_model(vec![prefixed_query], None)?;
+        return embeddings.pop().ok_or(EmbeddingError::MissingEmbedding);
     }
 
     pub fn list_available_embeddings_model() {
@@ -91,8 +372,359 @@ impl FastEmbedStruct{
     }
 
     pub fn get_current_model_size(&self) -> u64{
-        let model_size: &u64 = &self.available_model_info[&self.current_model_name]["model_size"].parse().unwrap();
-        log::info!("Model size is {:?}", model_size);
-        return *model_size;
+        log::info!("Model size is {:?}", self.current_model_size);
+        return self.current_model_size;
+    }
+}
+
+/// A file of a cached model, with its SHA-256 digest.
+#[derive(Debug, Clone)]
+pub struct CachedModelFile{
+    pub name: String,
+    pub size_bytes: u64,
+    pub sha256: String
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedModelReport{
+    pub model: FastEmbedModel,
+    pub model_dir: PathBuf,
+    /// The manifest every file's digest was checked against, or `None` when the model's
+    /// directory has no `MODEL_DIGEST_MANIFEST` and only the structure and loading of the
+    /// model were checked.
+    pub manifest_path: Option<PathBuf>,
+    pub files: Vec<CachedModelFile>,
+    pub dimension: usize
+}
+
+impl CachedModelReport{
+    pub fn describe(&self) -> String{
+        let mut description = match &self.manifest_path {
+            Some(manifest_path) => format!("Model {} at {:?} matches {:?} and is usable, dimension {}\n", self.model, self.model_dir, manifest_path, self.dimension),
+            None => format!(
+                "Model {} at {:?} is complete and usable, dimension {}\nDigests were NOT verified: there is no {} manifest, run `notion-llm model pin {}` to create one from these files\n",
+                self.model, self.model_dir, self.dimension, MODEL_DIGEST_MANIFEST, self.model
+            )
+        };
+        for file in self.files.iter(){
+            description.push_str(&format!("  {} {} bytes sha256={}\n", file.name, file.size_bytes, file.sha256));
+        }
+        return description;
+    }
+}
+
+/// Files of `MODEL_FILES` that are absent or empty in `model_dir`.
+fn missing_model_files(model_dir: &Path) -> Vec<String>{
+    return MODEL_FILES.iter()
+        .filter(|x| !std::fs::metadata(model_dir.join(x)).is_ok_and(|metadata| metadata.is_file() && metadata.len() > 0))
+        .map(|x| x.to_string())
+        .collect();
+}
+
+/// Checks that the JSON files of a cached model parse and hold the fields fastembed reads,
+/// since fastembed panics rather than errors on them.
+fn check_model_fields(model: FastEmbedModel, model_dir: &Path) -> Result<(), EmbeddingError>{
+    let corrupt_file = |file_name: &str, reason: String| EmbeddingError::CorruptModelFile{ model: model.to_string(), file: model_dir.join(file_name), reason };
+
+    for (file_name, field_name) in REQUIRED_MODEL_FIELDS{
+        let file = File::open(model_dir.join(file_name)).map_err(|err| corrupt_file(file_name, err.to_string()))?;
+        let json_value: serde_json::Value = serde_json::from_reader(file).map_err(|err| corrupt_file(file_name, err.to_string()))?;
+        if !field_name.is_empty() && json_value.get(field_name).is_none() {
+            return Err(corrupt_file(file_name, format!("field {:?} is missing", field_name)));
+        }
+    }
+    return Ok(());
+}
+
+fn sha256_file(file_path: &Path) -> std::io::Result<String>{
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(file_path)?, &mut hasher)?;
+    return Ok(hasher.finalize().iter().map(|x| format!("{:02x}", x)).collect());
+}
+
+/// Reads a digest manifest in the format written by `sha256sum`, one
+/// `<hex digest>  <file>` line per file, into file name -> lowercase digest.
+pub fn read_digest_manifest(manifest_path: &Path) -> Result<HashMap<String, String>, EmbeddingError>{
+    let manifest_error = |reason: String| EmbeddingError::DigestManifest{ path: manifest_path.to_path_buf(), reason };
+    let manifest_text = std::fs::read_to_string(manifest_path).map_err(|err| manifest_error(err.to_string()))?;
+
+    let mut digests = HashMap::new();
+    for (line_idx, line) in manifest_text.lines().enumerate().filter(|x| !x.1.trim().is_empty()){
+        let (digest, file_path) = match line.trim().split_once(char::is_whitespace) {
+            Some((digest, file_path)) if digest.len() == 64 && digest.chars().all(|x| x.is_ascii_hexdigit()) => (digest, file_path),
+            _ => return Err(manifest_error(format!("line {} is not `<sha256>  <file>`", line_idx + 1)))
+        };
+
+        // `sha256sum -b` marks files with `*`, and paths may include the model directory.
+        let file_path = file_path.trim().trim_start_matches('*');
+        let file_name = Path::new(file_path).file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+        digests.insert(file_name, digest.to_ascii_lowercase());
+    }
+
+    return Ok(digests);
+}
+
+/// Hashes every file of a cached model, after checking that none is missing.
+fn hash_model_files(model: FastEmbedModel, model_dir: &Path) -> Result<Vec<CachedModelFile>, EmbeddingError>{
+    let missing_files = missing_model_files(model_dir);
+    if !missing_files.is_empty() {
+        return Err(EmbeddingError::ModelNotCached{ model: model.to_string(), model_dir: model_dir.to_path_buf(), missing_files });
+    }
+
+    let mut files = Vec::new();
+    for file_name in MODEL_FILES{
+        let file_path = model_dir.join(file_name);
+        let corrupt_file = |err: std::io::Error| EmbeddingError::CorruptModelFile{ model: model.to_string(), file: file_path.clone(), reason: err.to_string() };
+
+        let sha256 = sha256_file(&file_path).map_err(corrupt_file)?;
+        let size_bytes = std::fs::metadata(&file_path).map_err(corrupt_file)?.len();
+        files.push(CachedModelFile{ name: file_name.to_string(), size_bytes, sha256 });
+    }
+    return Ok(files);
+}
+
+/// Writes the SHA-256 digests of the files of `model`, as currently cached in `cache_dir`,
+/// to `manifest_path` (by default `MODEL_DIGEST_MANIFEST` in the model's directory) in the
+/// format of `sha256sum`. Run it once on a trusted copy of the model, so later
+/// `verify_cached_model` calls detect any change to it. Returns the manifest's path.
+pub fn pin_cached_model(cache_dir: &Path, model: FastEmbedModel, manifest_path: Option<&Path>) -> Result<PathBuf, EmbeddingError>{
+    let model_dir = model.model_dir(cache_dir);
+    let manifest_path = match manifest_path {
+        Some(manifest_path) => manifest_path.to_path_buf(),
+        None => model_dir.join(MODEL_DIGEST_MANIFEST)
+    };
+
+    let files = hash_model_files(model, &model_dir)?;
+    check_model_fields(model, &model_dir)?;
+
+    let manifest_text: String = files.iter().map(|x| format!("{}  {}\n", x.sha256, x.name)).collect();
+    std::fs::write(&manifest_path, manifest_text).map_err(|err| EmbeddingError::DigestManifest{ path: manifest_path.clone(), reason: err.to_string() })?;
+
+    return Ok(manifest_path);
+}
+
+/// Checks that `model` is fully and correctly cached in `cache_dir` without touching the
+/// network: all files are present and match the SHA-256 digests of `manifest_path` (by
+/// default `MODEL_DIGEST_MANIFEST` in the model's directory), the JSON files hold the
+/// fields fastembed reads, the model loads, and it embeds text to vectors of the
+/// expected dimension.
+///
+/// fastembed does not write a manifest, so when none is given and the model's directory
+/// has none either, the digests are reported but not checked; see `pin_cached_model`.
+pub fn verify_cached_model(cache_dir: &Path, model: FastEmbedModel, manifest_path: Option<&Path>) -> Result<CachedModelReport, EmbeddingError>{
+    let model_dir = model.model_dir(cache_dir);
+    let manifest_path = match manifest_path {
+        Some(manifest_path) => Some(manifest_path.to_path_buf()),
+        None => Some(model_dir.join(MODEL_DIGEST_MANIFEST)).filter(|x| x.exists())
+    };
+
+    let files = hash_model_files(model, &model_dir)?;
+
+    match &manifest_path {
+        Some(manifest_path) => {
+            let expected_digests = read_digest_manifest(manifest_path)?;
+            for file in files.iter(){
+                let expected_digest = match expected_digests.get(&file.name) {
+                    Some(expected_digest) => expected_digest,
+                    None => return Err(EmbeddingError::DigestManifest{ path: manifest_path.clone(), reason: format!("no digest for {}", file.name) })
+                };
+                if &file.sha256 != expected_digest {
+                    return Err(EmbeddingError::CorruptModelFile{
+                        model: model.to_string(),
+                        file: model_dir.join(&file.name),
+                        reason: format!("SHA-256 is {} but the manifest expects {}", file.sha256, expected_digest)
+                    });
+                }
+            }
+        }
+        None => log::warn!("No {} manifest in {:?}, checking the structure of model {} only", MODEL_DIGEST_MANIFEST, model_dir, model)
+    }
+
+    // Building checks the JSON fields fastembed reads before loading the model.
+    let loaded_model = FastEmbedStruct::builder().model(model).cache_dir(cache_dir).offline(true).build()?;
+    let embedding = loaded_model.embed_query("notion-llm model verification")?;
+    if embedding.len() as u64 != loaded_model.current_model_size {
+        return Err(EmbeddingError::CorruptModelFile{
+            model: model.to_string(),
+            file: model_dir.join(MODEL_FILES[0]),
+            reason: format!("produced vectors of dimension {} instead of {}", embedding.len(), loaded_model.current_model_size)
+        });
+    }
+
+    return Ok(CachedModelReport{ model, model_dir, manifest_path, files, dimension: embedding.len() });
+}
+
+impl Embedder for FastEmbedStruct{
+    fn embed_documents(&self, documents: Vec<String>, batch_size: Option<usize>) -> Result<Vec<Vec<f32>>, EmbeddingError>{
+        return self.embed_passages(documents, batch_size);
+    }
+
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>, EmbeddingError>{
+        return FastEmbedStruct::embed_query(self, query);
+    }
+
+    fn dimension(&self) -> u64{
+        return self.get_current_model_size();
+    }
+
+    fn model_id(&self) -> &str{
+        return &self.model_id;
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// A model directory holding a small stand-in for every model file, in a fresh
+    /// temporary cache directory.
+    fn fake_cached_model(test_name: &str, model: FastEmbedModel) -> (PathBuf, HashMap<String, String>){
+        let cache_dir = std::env::temp_dir().join(format!("notion-llm-{}-{}", test_name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        let model_dir = model.model_dir(&cache_dir);
+        std::fs::create_dir_all(&model_dir).unwrap();
+
+        let mut digests = HashMap::new();
+        for file_name in MODEL_FILES{
+            std::fs::write(model_dir.join(file_name), format!("contents of {}", file_name)).unwrap();
+            digests.insert(file_name.to_string(), sha256_file(&model_dir.join(file_name)).unwrap());
+        }
+        return (cache_dir, digests);
+    }
+
+    /// Replaces the JSON files of a fake cached model with ones fastembed could read,
+    /// except for `tokenizer_config`.
+    fn write_model_configs(model_dir: &Path, tokenizer_config: serde_json::Value){
+        std::fs::write(model_dir.join("config.json"), r#"{"pad_token_id": 0}"#).unwrap();
+        std::fs::write(model_dir.join("special_tokens_map.json"), "{}").unwrap();
+        std::fs::write(model_dir.join("tokenizer_config.json"), tokenizer_config.to_string()).unwrap();
+    }
+
+    fn write_manifest(manifest_path: &Path, digests: &HashMap<String, String>){
+        let manifest_text: String = digests.iter().map(|(x, y)| format!("{}  ./{}\n", y, x)).collect();
+        std::fs::write(manifest_path, manifest_text).unwrap();
+    }
+
+    #[test]
+    fn digest_manifest_accepts_sha256sum_output(){
+        let (cache_dir, _) = fake_cached_model("manifest-format", FastEmbedModel::BGESmallEN);
+        let manifest_path = cache_dir.join("SHA256SUMS");
+        let digest = "AB".repeat(32);
+        std::fs::write(&manifest_path, format!("{}  fast-bge-small-en/config.json\n{} *tokenizer.json\n\n", digest, "cd".repeat(32))).unwrap();
+
+        let digests = read_digest_manifest(&manifest_path).unwrap();
+        assert_eq!(digests.get("config.json"), Some(&digest.to_ascii_lowercase()));
+        assert_eq!(digests.get("tokenizer.json"), Some(&"cd".repeat(32)));
+
+        std::fs::write(&manifest_path, "not-a-digest  config.json\n").unwrap();
+        assert!(matches!(read_digest_manifest(&manifest_path), Err(EmbeddingError::DigestManifest{ .. })));
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn verification_fails_on_a_digest_mismatch(){
+        let model = FastEmbedModel::BGESmallEN;
+        let (cache_dir, mut digests) = fake_cached_model("digest-mismatch", model);
+        digests.insert("tokenizer.json".to_string(), "0".repeat(64));
+        write_manifest(&model.model_dir(&cache_dir).join(MODEL_DIGEST_MANIFEST), &digests);
+
+        match verify_cached_model(&cache_dir, model, None) {
+            Err(EmbeddingError::CorruptModelFile{ file, .. }) => assert!(file.ends_with("tokenizer.json")),
+            other => panic!("expected a corrupt tokenizer.json, got {:?}", other.map(|x| x.describe()))
+        }
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn verification_needs_a_digest_for_every_file(){
+        let model = FastEmbedModel::BGESmallEN;
+        let (cache_dir, mut digests) = fake_cached_model("digest-missing", model);
+        digests.remove("model_optimized.onnx");
+        let manifest_path = cache_dir.join("provisioned.sha256");
+        write_manifest(&manifest_path, &digests);
+
+        assert!(matches!(verify_cached_model(&cache_dir, model, Some(&manifest_path)), Err(EmbeddingError::DigestManifest{ .. })));
+        // Only the default manifest may be absent; a given one has to exist.
+        assert!(matches!(verify_cached_model(&cache_dir, model, Some(&cache_dir.join("absent.sha256"))), Err(EmbeddingError::DigestManifest{ .. })));
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn offline_loading_fails_clearly_without_cached_files(){
+        let model = FastEmbedModel::BGESmallEN;
+        let (cache_dir, _) = fake_cached_model("offline-missing", model);
+        std::fs::remove_file(model.model_dir(&cache_dir).join("tokenizer.json")).unwrap();
+
+        match FastEmbedStruct::builder().model(model).cache_dir(&cache_dir).offline(true).build() {
+            Err(EmbeddingError::ModelNotCached{ missing_files, .. }) => assert_eq!(missing_files, vec!["tokenizer.json".to_string()]),
+            Err(err) => panic!("expected ModelNotCached, got {}", err),
+            Ok(_) => panic!("expected ModelNotCached, but the model loaded")
+        }
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn offline_loading_rejects_configs_fastembed_would_panic_on(){
+        let model = FastEmbedModel::BGESmallEN;
+        let (cache_dir, _) = fake_cached_model("offline-fields", model);
+        write_model_configs(&model.model_dir(&cache_dir), serde_json::json!({ "model_max_length": 512 }));
+
+        match FastEmbedStruct::builder().model(model).cache_dir(&cache_dir).offline(true).build() {
+            Err(EmbeddingError::CorruptModelFile{ file, reason, .. }) => {
+                assert!(file.ends_with("tokenizer_config.json"));
+                assert!(reason.contains("pad_token"), "{}", reason);
+            }
+            Err(err) => panic!("expected CorruptModelFile, got {}", err),
+            Ok(_) => panic!("expected CorruptModelFile, but the model loaded")
+        }
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn pinned_digests_catch_later_changes(){
+        let model = FastEmbedModel::BGESmallEN;
+        let (cache_dir, _) = fake_cached_model("pin", model);
+        let model_dir = model.model_dir(&cache_dir);
+        write_model_configs(&model_dir, serde_json::json!({ "model_max_length": 512, "pad_token": "[PAD]" }));
+
+        let manifest_path = pin_cached_model(&cache_dir, model, None).unwrap();
+        assert_eq!(manifest_path, model_dir.join(MODEL_DIGEST_MANIFEST));
+        let pinned_digests = read_digest_manifest(&manifest_path).unwrap();
+        for file_name in MODEL_FILES{
+            assert_eq!(pinned_digests.get(file_name), Some(&sha256_file(&model_dir.join(file_name)).unwrap()));
+        }
+
+        std::fs::write(model_dir.join("model_optimized.onnx"), "tampered").unwrap();
+        match verify_cached_model(&cache_dir, model, None) {
+            Err(EmbeddingError::CorruptModelFile{ file, .. }) => assert!(file.ends_with("model_optimized.onnx")),
+            other => panic!("expected a corrupt model_optimized.onnx, got {:?}", other.map(|x| x.describe()))
+        }
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn verification_without_a_manifest_checks_the_structure_and_says_so(){
+        let model = FastEmbedModel::BGESmallEN;
+        let (cache_dir, _) = fake_cached_model("no-manifest", model);
+        let model_dir = model.model_dir(&cache_dir);
+        write_model_configs(&model_dir, serde_json::json!({ "pad_token": "[PAD]" }));
+
+        // Without a manifest the digests are skipped, but the structural checks still run.
+        match verify_cached_model(&cache_dir, model, None) {
+            Err(EmbeddingError::CorruptModelFile{ file, .. }) => assert!(file.ends_with("tokenizer_config.json")),
+            other => panic!("expected a corrupt tokenizer_config.json, got {:?}", other.map(|x| x.describe()))
+        }
+
+        let report = CachedModelReport{ model, model_dir: model_dir.clone(), manifest_path: None, files: Vec::new(), dimension: 384 };
+        assert!(report.describe().contains("Digests were NOT verified"), "{}", report.describe());
+        assert!(report.describe().contains("notion-llm model pin BGESmallEN"), "{}", report.describe());
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
     }
 }
@@ -1,3 +1,19 @@
+// Explicit `return` statements are the house style across the crate.
+#![allow(clippy::needless_return)]
+
 pub mod notion_pages_setup;
+pub mod notion_markdown;
+pub mod notion_types;
+pub mod notion_properties;
+pub mod notion_error;
+pub mod notion_rate_limit;
+pub mod notion_crawler;
+pub mod notion_sync;
+pub mod sync_state_store;
+pub mod text_chunker;
+pub mod block_chunker;
+pub mod point_ids;
+pub mod embedder;
+pub mod embedding_cache;
 pub mod fast_embed;
 pub mod qdrantdb;
@@ -1,65 +1,200 @@
-use std::collections::HashMap;
+#![allow(clippy::needless_return)]
+
 use env_logger::Builder;
-use notion_llm::notion_pages_setup::NotionPagesAPI;
+use notion_llm::embedding_cache::DEFAULT_EMBEDDING_CACHE_PATH;
+use notion_llm::fast_embed::{pin_cached_model, verify_cached_model, FastEmbedModel, FastEmbedStruct, DEFAULT_MODEL_CACHE_DIR};
+use notion_llm::notion_crawler::NotionCrawler;
+use notion_llm::notion_pages_setup::{NotionPagesAPI, SearchObjectType, SearchQuery, SortDirection};
+use notion_llm::notion_sync::{PageToIndex, SyncEngine};
+use notion_llm::notion_types::SearchResult;
 use notion_llm::qdrantdb::QdrantDBStruct;
+use notion_llm::sync_state_store::{SyncStateStore, DEFAULT_SYNC_STATE_PATH};
+use std::path::Path;
+use std::sync::Arc;
 
-#[tokio::main]
-async fn main() {
-    Builder::new().filter_level(log::LevelFilter::Info).init();
-    println!("Hello, world!");
-
-    let qdb = QdrantDBStruct::new(None, None);
+/// Directory embedding models are loaded from, when set.
+const MODEL_CACHE_DIR_ENV: &str = "NOTION_LLM_MODEL_CACHE_DIR";
+/// When set to `1` or `true`, models are never downloaded and must already be cached.
+const OFFLINE_ENV: &str = "NOTION_LLM_OFFLINE";
 
-    let _ = qdb.create_collection(&String::from("notion-llm-cooking")).await;
+fn model_cache_dir() -> String{
+    return std::env::var(MODEL_CACHE_DIR_ENV).unwrap_or_else(|_| DEFAULT_MODEL_CACHE_DIR.to_string());
+}
 
-    let mut npi = NotionPagesAPI::new();
+fn is_offline() -> bool{
+    return std::env::var(OFFLINE_ENV).is_ok_and(|x| x == "1" || x.eq_ignore_ascii_case("true"));
+}
 
-    let child_pages_api_call = npi.get_children("34e444a4324c4e98b5f01d0965580fb2").await;
+/// `state inspect` prints the sync state, `state reset [collection]` clears it.
+fn run_state_command(state_args: &[String]){
+    let mut sync_state = match SyncStateStore::open(DEFAULT_SYNC_STATE_PATH) {
+        Ok(sync_state) => sync_state,
+        Err(err) => {
+            println!("Errored due to {:?}", err);
+            return;
+        }
+    };
 
-    let child_pages: Option<HashMap<String, String>>;
+    match state_args.first().map(|x| x.as_str()) {
+        Some("inspect") => {
+            print!("{}", sync_state.describe());
+        }
+        Some("reset") => {
+            let removed_pages = match state_args.get(1) {
+                Some(collection_name) => sync_state.reset_collection(collection_name),
+                None => sync_state.reset()
+            };
+            if let Err(err) = sync_state.save() {
+                println!("Errored due to {:?}", err);
+                return;
+            }
+            println!("Forgot {} indexed pages; points already in Qdrant were left untouched", removed_pages);
+        }
+        _ => {
+            println!("Usage: notion-llm state inspect | notion-llm state reset [collection]");
+        }
+    }
+}
 
-    match child_pages_api_call{
-        Ok(child_pages_api_call) => {
-            println!("child pages are {:?}", &child_pages_api_call);
-            child_pages = Some(child_pages_api_call);
-            
+/// `model verify <name> [cache_dir] [manifest]` checks a cached model against a
+/// `sha256sum` manifest, by default `SHA256SUMS` in the model's directory, and loads it.
+/// Without a manifest only the files and loading are checked. `model pin <name>
+/// [cache_dir] [manifest]` writes the manifest from the files as currently cached.
+fn run_model_command(model_args: &[String]){
+    let (model_command, model_name) = match (model_args.first().map(|x| x.as_str()), model_args.get(1)) {
+        (Some(model_command @ ("verify" | "pin")), Some(model_name)) => (model_command, model_name),
+        _ => {
+            println!("Usage: notion-llm model verify|pin <model> [cache_dir] [sha256_manifest]");
+            return;
         }
+    };
+    let model: FastEmbedModel = match model_name.parse() {
+        Ok(model) => model,
         Err(err) => {
-            child_pages = None;
-            println!("Errored due to {:?}", err);
+            println!("Errored due to {}", err);
+            return;
+        }
+    };
+    let cache_dir = model_args.get(2).cloned().unwrap_or_else(model_cache_dir);
+
+    let manifest_path = model_args.get(3).map(Path::new);
+
+    if model_command == "pin" {
+        match pin_cached_model(cache_dir.as_ref(), model, manifest_path) {
+            Ok(manifest_path) => println!("Pinned the digests of model {} in {:?}", model, manifest_path),
+            Err(err) => println!("Errored due to {}", err)
         }
+        return;
     }
 
-    let mut page_contents: HashMap<String, String> = HashMap::new();
+    match verify_cached_model(cache_dir.as_ref(), model, manifest_path) {
+        Ok(report) => print!("{}", report.describe()),
+        Err(err) => println!("Errored due to {}", err)
+    }
+}
 
-    // let mut qdrant_docs: Vec<String> = Vec::new();
-    // let mut qdrant_metadata: Vec<HashMap<String, String>> = Vec::new();
+#[tokio::main]
+async fn main() {
+    Builder::new().filter_level(log::LevelFilter::Info).init();
+    println!("Hello, world!");
 
-    if let Some(child_pages) = &child_pages {
-        for (page_key, page_id) in child_pages.iter(){
-            let content = npi.get_page_content(page_id).await;
-
-            match content{
-                Ok(content) => {
-                    page_contents.insert(page_key.clone(), content.clone());
-                    let qdrant_docs = vec![content];
-                    let qdrant_metadata = vec![HashMap::from([("dish_name".to_string(), page_key.to_string())])];
-                    let id_for_stuff = QdrantDBStruct::create_ids(vec![page_key.to_string()]);
-
-                    let _ = qdb.add_stuff_to_collection(
-                        "notion-llm-cooking",
-                        qdrant_docs.clone(),
-                        id_for_stuff,
-                        qdrant_metadata.clone()
-                    ).await;
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|x| x.as_str()) == Some("state") {
+        run_state_command(&args[2..]);
+        return;
+    }
+    if args.get(1).map(|x| x.as_str()) == Some("model") {
+        run_model_command(&args[2..]);
+        return;
+    }
+
+    let embeddings_model = match FastEmbedStruct::builder()
+        .cache_dir(model_cache_dir())
+        .offline(is_offline())
+        .embedding_cache(DEFAULT_EMBEDDING_CACHE_PATH)
+        .build() {
+        Ok(embeddings_model) => Arc::new(embeddings_model),
+        Err(err) => {
+            println!("Errored due to {}", err);
+            return;
+        }
+    };
+    let qdb = QdrantDBStruct::new_with_embedder(None, embeddings_model.clone());
+
+    if let Err(err) = qdb.create_collection("notion-llm-cooking").await {
+        println!("Errored due to {}", err);
+        return;
+    }
+
+    let npi = match NotionPagesAPI::new() {
+        Ok(npi) => npi,
+        Err(err) => {
+            println!("Errored due to {:?}", err);
+            return;
+        }
+    };
+
+    // Crawl below the given root page, or index every page shared with the integration.
+    let root_page_id = args.get(1).cloned();
 
+    let child_pages: Option<Vec<PageToIndex>> = match &root_page_id {
+        Some(root_page_id) => {
+            match NotionCrawler::new(&npi).crawl(root_page_id).await {
+                Ok(hierarchy) => Some(hierarchy.child_pages().map(|x| PageToIndex{
+                    id: x.id.clone(),
+                    title: x.title.clone(),
+                    path: x.path_string(),
+                    properties: x.properties.clone()
+                }).collect()),
+                Err(err) => {
+                    println!("Errored due to {:?}", err);
+                    None
                 }
+            }
+        }
+        None => {
+            let search_query = SearchQuery::new()
+                .object_type(SearchObjectType::Page)
+                .sort_by_last_edited(SortDirection::Descending);
+
+            match npi.search(&search_query).await {
+                Ok(search_results) => Some(search_results.into_iter().filter_map(|x| {
+                    match x {
+                        SearchResult::Page(page) if !page.archived && !page.in_trash => {
+                            let title = page.title();
+                            Some(PageToIndex{
+                                id: page.id,
+                                title: title.clone(),
+                                path: title,
+                                properties: page.properties
+                            })
+                        }
+                        _ => None
+                    }
+                }).collect()),
                 Err(err) => {
                     println!("Errored due to {:?}", err);
+                    None
                 }
             }
         }
+    };
+
+    if let Some(child_pages) = &child_pages {
+        println!("child pages are {:?}", child_pages.iter().map(|x| &x.path).collect::<Vec<&String>>());
+
+        let sync_result = SyncEngine::new(&npi, &qdb, "notion-llm-cooking").sync(child_pages).await;
+
+        match sync_result{
+            Ok(sync_report) => {
+                println!("Sync report: {:?}", sync_report);
+                if let Some(cache_stats) = embeddings_model.embedding_cache_stats() {
+                    println!("Embedding cache: {}", cache_stats);
+                }
+            }
+            Err(err) => {
+                println!("Errored due to {:?}", err);
+            }
+        }
     }
-    
-    println!("Contents of pages are {:?}", page_contents);
 }
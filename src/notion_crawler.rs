@@ -0,0 +1,337 @@
+use futures::future::BoxFuture;
+use std::collections::{HashMap, HashSet, VecDeque};
+use crate::notion_error::NotionError;
+use crate::notion_pages_setup::{DatabaseQuery, NotionPagesAPI, DEFAULT_MAX_BLOCK_DEPTH};
+use crate::notion_properties::PropertyValue;
+use crate::notion_types::{normalize_notion_id, BlockType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CrawledObjectType{
+    Page,
+    Database
+}
+
+/// A page or database discovered below the crawl root.
+#[derive(Debug, Clone)]
+pub struct CrawledPage{
+    pub id: String,
+    pub title: String,
+    pub object_type: CrawledObjectType,
+    /// ID of the page this one was found in; the root for top-level pages.
+    pub parent_id: String,
+    /// Titles from the top-level page down to and including this page.
+    pub path: Vec<String>,
+    /// IDs from the root down to (but excluding) this page.
+    pub ancestor_ids: Vec<String>,
+    /// IDs of the pages and databases discovered directly inside this page.
+    pub child_ids: Vec<String>,
+    /// Property values of database rows; empty for regular pages and databases.
+    pub properties: HashMap<String, PropertyValue>
+}
+
+impl CrawledPage{
+    pub fn depth(&self) -> usize{
+        return self.path.len();
+    }
+
+    /// The title path joined with ` / `, e.g. `Recipes / Italian / Carbonara`.
+    pub fn path_string(&self) -> String{
+        return self.path.join(" / ");
+    }
+}
+
+/// Every page and database discovered below a root page, in breadth-first order.
+#[derive(Debug, Clone, Default)]
+pub struct PageHierarchy{
+    pub root_id: String,
+    pub pages: Vec<CrawledPage>,
+    page_index: HashMap<String, usize>
+}
+
+impl PageHierarchy{
+    pub fn get(&self, page_id: &str) -> Option<&CrawledPage>{
+        return self.page_index.get(&normalize_notion_id(page_id)).map(|x| &self.pages[*x]);
+    }
+
+    /// Pages and databases found directly inside `page_id`, which may be the root.
+    pub fn children_of(&self, page_id: &str) -> Vec<&CrawledPage>{
+        let page_id = normalize_notion_id(page_id);
+        return self.pages.iter().filter(|x| normalize_notion_id(&x.parent_id) == page_id).collect();
+    }
+
+    /// Only the pages, skipping databases.
+    pub fn child_pages(&self) -> impl Iterator<Item = &CrawledPage>{
+        return self.pages.iter().filter(|x| x.object_type == CrawledObjectType::Page);
+    }
+
+    pub fn len(&self) -> usize{
+        return self.pages.len();
+    }
+
+    pub fn is_empty(&self) -> bool{
+        return self.pages.is_empty();
+    }
+}
+
+/// Walks a root page to discover every descendant page and database.
+///
+/// Child pages are found wherever they sit in a page, including inside toggles, columns
+/// and synced blocks, and regardless of whether they have content themselves. The rows
+/// of child databases are discovered as pages too, unless disabled.
+pub struct NotionCrawler<'a>{
+    notion_api: &'a NotionPagesAPI,
+    max_depth: Option<usize>,
+    max_block_depth: usize,
+    include_database_rows: bool
+}
+
+impl<'a> NotionCrawler<'a>{
+    pub fn new(notion_api: &'a NotionPagesAPI) -> Self{
+        return NotionCrawler{
+            notion_api,
+            max_depth: None,
+            max_block_depth: DEFAULT_MAX_BLOCK_DEPTH,
+            include_database_rows: true
+        };
+    }
+
+    /// Whether to query child databases and crawl their rows.
+    pub fn include_database_rows(mut self, include_database_rows: bool) -> Self{
+        self.include_database_rows = include_database_rows;
+        return self;
+    }
+
+    /// Stops descending after `max_depth` levels of pages; top-level pages are depth 1.
+    pub fn max_depth(mut self, max_depth: usize) -> Self{
+        self.max_depth = Some(max_depth);
+        return self;
+    }
+
+    /// How deep to look inside non-page blocks (toggles, columns...) for child pages.
+    pub fn max_block_depth(mut self, max_block_depth: usize) -> Self{
+        self.max_block_depth = max_block_depth;
+        return self;
+    }
+
+    pub async fn crawl(&self, root_page_id: &str) -> Result<PageHierarchy, NotionError>{
+        log::info!("Crawling Notion pages below {}", root_page_id);
+
+        let mut hierarchy = PageHierarchy{
+            root_id: root_page_id.to_string(),
+            ..Default::default()
+        };
+
+        let mut visited: HashSet<String> = HashSet::from([normalize_notion_id(root_page_id)]);
+        // (page ID, index of the page in the hierarchy, `None` for the root)
+        let mut pages_to_visit: VecDeque<(String, Option<usize>)> = VecDeque::from([(root_page_id.to_string(), None)]);
+
+        while let Some((page_id, page_idx)) = pages_to_visit.pop_front() {
+            let (parent_path, parent_ancestors) = match page_idx {
+                Some(page_idx) => {
+                    let parent = &hierarchy.pages[page_idx];
+                    let mut ancestor_ids = parent.ancestor_ids.clone();
+                    ancestor_ids.push(parent.id.clone());
+                    (parent.path.clone(), ancestor_ids)
+                }
+                None => (Vec::new(), vec![page_id.clone()])
+            };
+
+            let is_database = match page_idx {
+                Some(page_idx) => hierarchy.pages[page_idx].object_type == CrawledObjectType::Database,
+                None => false
+            };
+
+            let child_objects = match is_database {
+                // Linked databases and databases not shared with the integration cannot be
+                // queried; only their rows are lost, not the rest of the crawl.
+                true => match self.find_database_rows(&page_id).await {
+                    Ok(database_rows) => database_rows,
+                    Err(err @ (NotionError::NotFound{ .. } | NotionError::Validation{ .. })) => {
+                        log::warn!("Skipping rows of database {}: {}", page_id, err);
+                        Vec::new()
+                    }
+                    Err(err) => return Err(err)
+                },
+                false => self.find_child_objects(&page_id, self.max_block_depth).await?
+            };
+
+            for child_object in child_objects{
+                let DiscoveredObject{ id: child_id, title, object_type, properties } = child_object;
+
+                if !visited.insert(normalize_notion_id(&child_id)) {
+                    log::debug!("Skipping already discovered page {}", child_id);
+                    continue;
+                }
+
+                let mut path = parent_path.clone();
+                path.push(title.clone());
+
+                let child_idx = hierarchy.pages.len();
+                hierarchy.page_index.insert(normalize_notion_id(&child_id), child_idx);
+                hierarchy.pages.push(CrawledPage{
+                    id: child_id.clone(),
+                    title,
+                    object_type,
+                    parent_id: page_id.clone(),
+                    path,
+                    ancestor_ids: parent_ancestors.clone(),
+                    child_ids: Vec::new(),
+                    properties
+                });
+
+                if let Some(page_idx) = page_idx {
+                    hierarchy.pages[page_idx].child_ids.push(child_id.clone());
+                }
+
+                let within_max_depth = match self.max_depth {
+                    Some(max_depth) => hierarchy.pages[child_idx].depth() < max_depth,
+                    None => true
+                };
+                let should_visit = match object_type {
+                    CrawledObjectType::Page => true,
+                    CrawledObjectType::Database => self.include_database_rows
+                };
+                if should_visit && within_max_depth {
+                    pages_to_visit.push_back((child_id, Some(child_idx)));
+                }
+            }
+        }
+
+        log::info!("Discovered {} pages and databases below {}", hierarchy.len(), root_page_id);
+        return Ok(hierarchy);
+    }
+
+    /// Turns every row of `database_id` into a discovered page carrying its properties.
+    async fn find_database_rows(&self, database_id: &str) -> Result<Vec<DiscoveredObject>, NotionError>{
+        let database_rows = self.notion_api.query_database(database_id, &DatabaseQuery::new()).await?;
+
+        return Ok(database_rows.into_iter().filter(|x| !x.archived && !x.in_trash).map(|x| {
+            return DiscoveredObject{
+                title: x.title(),
+                id: x.id,
+                object_type: CrawledObjectType::Page,
+                properties: x.properties
+            };
+        }).collect());
+    }
+
+    /// Collects the `child_page` and `child_database` blocks of `block_id`, descending
+    /// into other blocks with children since pages may be nested inside them.
+    fn find_child_objects(&'a self, block_id: &'a str, max_block_depth: usize) -> BoxFuture<'a, Result<Vec<DiscoveredObject>, NotionError>>{
+        return Box::pin(async move {
+            let blocks = self.notion_api.get_all_block_children(block_id).await?;
+            let mut child_objects: Vec<DiscoveredObject> = Vec::new();
+
+            for block in blocks{
+                match block.block_type {
+                    BlockType::ChildPage{ child_page } => {
+                        child_objects.push(DiscoveredObject{
+                            id: block.id,
+                            title: child_page.title,
+                            object_type: CrawledObjectType::Page,
+                            properties: HashMap::new()
+                        });
+                    }
+                    BlockType::ChildDatabase{ child_database } => {
+                        child_objects.push(DiscoveredObject{
+                            id: block.id,
+                            title: child_database.title,
+                            object_type: CrawledObjectType::Database,
+                            properties: HashMap::new()
+                        });
+                    }
+                    _ if block.has_children && max_block_depth > 0 => {
+                        child_objects.extend(self.find_child_objects(&block.id, max_block_depth - 1).await?);
+                    }
+                    _ => {}
+                }
+            }
+
+            return Ok(child_objects);
+        });
+    }
+}
+
+/// A page or database found while scanning a page or querying a database.
+struct DiscoveredObject{
+    id: String,
+    title: String,
+    object_type: CrawledObjectType,
+    properties: HashMap<String, PropertyValue>
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::notion_rate_limit::RetryPolicy;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn child_page_block(page_id: &str, title: &str) -> serde_json::Value{
+        return json!({ "object": "block", "id": page_id, "type": "child_page", "has_children": true, "child_page": { "title": title } });
+    }
+
+    fn container_block(block_id: &str, block_type: &str) -> serde_json::Value{
+        let mut block_json = json!({ "object": "block", "id": block_id, "type": block_type, "has_children": true });
+        block_json[block_type] = json!({});
+        return block_json;
+    }
+
+    async fn mount_children(mock_server: &MockServer, block_id: &str, children: Vec<serde_json::Value>){
+        Mock::given(method("GET"))
+            .and(path(format!("/v1/blocks/{}/children", block_id)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "object": "list", "results": children, "has_more": false })))
+            .mount(mock_server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn crawl_finds_nested_pages_once_and_skips_unqueryable_databases(){
+        let mock_server = MockServer::start().await;
+        let recipes_id = "11111111-2222-3333-4444-555555555555";
+
+        mount_children(&mock_server, "root", vec![
+            child_page_block(recipes_id, "Recipes"),
+            container_block("toggle-1", "toggle"),
+            container_block("columns-1", "column_list"),
+            json!({ "object": "block", "id": "database-1", "type": "child_database", "has_children": false, "child_database": { "title": "Linked" } })
+        ]).await;
+        // The same page linked again without dashes and in upper case.
+        mount_children(&mock_server, "toggle-1", vec![child_page_block(&recipes_id.replace('-', "").to_uppercase(), "Recipes")]).await;
+        mount_children(&mock_server, "columns-1", vec![container_block("column-1", "column")]).await;
+        mount_children(&mock_server, "column-1", vec![child_page_block("nested-page", "Nested")]).await;
+        mount_children(&mock_server, recipes_id, Vec::new()).await;
+        mount_children(&mock_server, "nested-page", Vec::new()).await;
+        Mock::given(method("POST"))
+            .and(path("/v1/databases/database-1/query"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "object": "error", "status": 404, "code": "object_not_found", "message": "Could not find database"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let notion_api = NotionPagesAPI::builder()
+            .token("secret")
+            .base_url(mock_server.uri())
+            .rate_limit(1000.0, 1000)
+            .retry_policy(RetryPolicy::disabled())
+            .build()
+            .unwrap();
+        let hierarchy = NotionCrawler::new(&notion_api).crawl("root").await.unwrap();
+
+        let discovered: Vec<(&str, CrawledObjectType)> = hierarchy.pages.iter().map(|x| (x.id.as_str(), x.object_type)).collect();
+        assert_eq!(discovered, vec![
+            (recipes_id, CrawledObjectType::Page),
+            ("nested-page", CrawledObjectType::Page),
+            ("database-1", CrawledObjectType::Database)
+        ]);
+
+        let nested_page = hierarchy.get("nested-page").unwrap();
+        assert_eq!(nested_page.parent_id, "root");
+        assert_eq!(nested_page.path_string(), "Nested");
+        assert!(hierarchy.get(&recipes_id.replace('-', "")).is_some());
+        assert_eq!(hierarchy.children_of("root").len(), 3);
+    }
+}
@@ -0,0 +1,161 @@
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Errors returned by `NotionPagesAPI`.
+#[derive(Debug, thiserror::Error)]
+pub enum NotionError{
+    /// The request never produced a response (DNS, TLS, connection reset, timeout...).
+    #[error("transport error while calling Notion: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// 401: the integration token is missing, invalid or revoked.
+    #[error("unauthorized ({code}): {message}")]
+    Unauthorized{ code: String, message: String },
+
+    /// 404: the object does not exist or has not been shared with the integration.
+    #[error("not found or not shared with the integration ({code}): {message}")]
+    NotFound{ code: String, message: String },
+
+    /// 429: Notion asked us to slow down, optionally telling us for how long.
+    #[error("rate limited by Notion (retry after {retry_after:?})")]
+    RateLimited{ retry_after: Option<Duration> },
+
+    /// 400: the request was rejected, e.g. `validation_error` or `invalid_request_url`.
+    #[error("validation error ({code}): {message}")]
+    Validation{ code: String, message: String },
+
+    /// Any other non-2xx response, such as 403 `restricted_resource` or 5xx errors.
+    #[error("Notion returned {status} ({code}): {message}")]
+    Api{ status: StatusCode, code: String, message: String },
+
+    /// The client could not be built from the given configuration.
+    #[error("invalid Notion client configuration: {0}")]
+    Config(String),
+
+    /// The response body could not be decoded into the expected type.
+    #[error("failed to decode Notion response: {0}")]
+    Decode(#[from] serde_json::Error)
+}
+
+/// Body of a Notion error response, e.g.
+/// `{"object": "error", "status": 400, "code": "validation_error", "message": "..."}`.
+#[derive(Debug, Default, Deserialize)]
+struct NotionErrorBody{
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    message: String
+}
+
+impl NotionError{
+    /// Whether the request may succeed if sent again: rate limiting, 5xx responses,
+    /// timeouts and connection failures.
+    pub fn is_retryable(&self) -> bool{
+        match self{
+            NotionError::RateLimited{ .. } => true,
+            NotionError::Api{ status, .. } => status.is_server_error(),
+            NotionError::Transport(err) => err.is_timeout() || err.is_connect(),
+            _ => false
+        }
+    }
+
+    /// Builds the matching error variant from a non-2xx response.
+    pub fn from_response(status: StatusCode, headers: &HeaderMap, response_body: &str) -> Self{
+        let error_body: NotionErrorBody = serde_json::from_str(response_body).unwrap_or_else(|_| NotionErrorBody{
+            code: String::new(),
+            message: response_body.to_string()
+        });
+
+        match status{
+            StatusCode::UNAUTHORIZED => NotionError::Unauthorized{ code: error_body.code, message: error_body.message },
+            StatusCode::NOT_FOUND => NotionError::NotFound{ code: error_body.code, message: error_body.message },
+            StatusCode::TOO_MANY_REQUESTS => NotionError::RateLimited{ retry_after: Self::parse_retry_after(headers) },
+            StatusCode::BAD_REQUEST => NotionError::Validation{ code: error_body.code, message: error_body.message },
+            _ => NotionError::Api{ status, code: error_body.code, message: error_body.message }
+        }
+    }
+
+    /// Notion sends `Retry-After` as a number of seconds. Values too large for a
+    /// `Duration` saturate; `RetryPolicy` caps the wait at its `max_delay` anyway.
+    fn parse_retry_after(headers: &HeaderMap) -> Option<Duration>{
+        let retry_after = headers.get(RETRY_AFTER)?.to_str().ok()?;
+        let seconds: f64 = retry_after.trim().parse().ok()?;
+        if !seconds.is_finite() || seconds < 0.0 {
+            return None;
+        }
+        return Some(Duration::try_from_secs_f64(seconds).unwrap_or(Duration::MAX));
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn error_body(code: &str) -> String{
+        return format!(r#"{{"object": "error", "status": 400, "code": "{}", "message": "details"}}"#, code);
+    }
+
+    fn rate_limited_after(retry_after: &str) -> NotionError{
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, retry_after.parse().unwrap());
+        return NotionError::from_response(StatusCode::TOO_MANY_REQUESTS, &headers, &error_body("rate_limited"));
+    }
+
+    #[test]
+    fn statuses_map_onto_their_variants(){
+        let headers = HeaderMap::new();
+
+        assert!(matches!(
+            NotionError::from_response(StatusCode::UNAUTHORIZED, &headers, &error_body("unauthorized")),
+            NotionError::Unauthorized{ code, message } if code == "unauthorized" && message == "details"
+        ));
+        assert!(matches!(NotionError::from_response(StatusCode::NOT_FOUND, &headers, &error_body("object_not_found")), NotionError::NotFound{ .. }));
+        assert!(matches!(NotionError::from_response(StatusCode::BAD_REQUEST, &headers, &error_body("validation_error")), NotionError::Validation{ .. }));
+        assert!(matches!(NotionError::from_response(StatusCode::TOO_MANY_REQUESTS, &headers, &error_body("rate_limited")), NotionError::RateLimited{ retry_after: None }));
+        assert!(matches!(
+            NotionError::from_response(StatusCode::FORBIDDEN, &headers, &error_body("restricted_resource")),
+            NotionError::Api{ status: StatusCode::FORBIDDEN, .. }
+        ));
+    }
+
+    #[test]
+    fn bodies_that_are_not_notion_errors_become_the_message(){
+        let error = NotionError::from_response(StatusCode::BAD_GATEWAY, &HeaderMap::new(), "<html>Bad Gateway</html>");
+
+        match &error {
+            NotionError::Api{ code, message, .. } => {
+                assert_eq!(code, "");
+                assert_eq!(message, "<html>Bad Gateway</html>");
+            }
+            other => panic!("expected Api, got {:?}", other)
+        }
+        assert!(error.is_retryable());
+    }
+
+    #[test]
+    fn only_transient_errors_are_retryable(){
+        assert!(rate_limited_after("1").is_retryable());
+        assert!(NotionError::from_response(StatusCode::SERVICE_UNAVAILABLE, &HeaderMap::new(), "").is_retryable());
+        assert!(!NotionError::from_response(StatusCode::CONFLICT, &HeaderMap::new(), "").is_retryable());
+        assert!(!NotionError::from_response(StatusCode::NOT_FOUND, &HeaderMap::new(), "").is_retryable());
+        assert!(!NotionError::Config("bad".to_string()).is_retryable());
+    }
+
+    #[test]
+    fn retry_after_is_parsed_in_seconds(){
+        assert!(matches!(rate_limited_after("2"), NotionError::RateLimited{ retry_after: Some(x) } if x == Duration::from_secs(2)));
+        assert!(matches!(rate_limited_after(" 0.5 "), NotionError::RateLimited{ retry_after: Some(x) } if x == Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn unusable_retry_after_values_are_ignored_or_saturate(){
+        for retry_after in ["soon", "Wed, 21 Oct 2015 07:28:00 GMT", "-1", "NaN", "inf"]{
+            assert!(matches!(rate_limited_after(retry_after), NotionError::RateLimited{ retry_after: None }), "{:?}", retry_after);
+        }
+
+        assert!(matches!(rate_limited_after("1e300"), NotionError::RateLimited{ retry_after: Some(Duration::MAX) }));
+        assert!(matches!(rate_limited_after("18446744073709551616"), NotionError::RateLimited{ retry_after: Some(Duration::MAX) }));
+    }
+}
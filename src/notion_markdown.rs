@@ -0,0 +1,381 @@
+use crate::notion_pages_setup::BlockNode;
+use crate::notion_types::{plain_text, BlockType, FileBlock, Icon, RichText, RichTextKind};
+use std::ops::Range;
+
+/// Converts a tree of Notion blocks into a Markdown document.
+pub fn blocks_to_markdown(nodes: &[BlockNode]) -> String{
+    let mut output = String::new();
+    render_blocks(nodes, 0, &mut output, None);
+    return output;
+}
+
+/// Like `blocks_to_markdown`, also returning the byte range of the document each
+/// top-level block was rendered into, children included. Slicing the document keeps list
+/// numbering intact, unlike rendering the blocks piecewise.
+pub fn blocks_to_markdown_with_spans(nodes: &[BlockNode]) -> (String, Vec<Range<usize>>){
+    let mut output = String::new();
+    let mut block_spans = Vec::with_capacity(nodes.len());
+    render_blocks(nodes, 0, &mut output, Some(&mut block_spans));
+    return (output, block_spans);
+}
+
+/// Renders a Notion `rich_text` array into Markdown, concatenating every segment and
+/// mapping bold, italic, strikethrough, code, links and inline equations.
+pub fn rich_text_to_markdown(rich_text: &[RichText]) -> String{
+    return rich_text.iter().map(rich_text_segment_to_markdown).collect();
+}
+
+/// Builds the canonical notion.so link for a page or database ID.
+pub fn notion_url(object_id: &str) -> String{
+    return format!("https://www.notion.so/{}", object_id.replace('-', ""));
+}
+
+fn rich_text_segment_to_markdown(segment: &RichText) -> String{
+    let plain_text = segment.plain_text.as_str();
+
+    if let RichTextKind::Equation{ equation } = &segment.kind {
+        return format!("${}$", equation.expression);
+    }
+
+    // Markdown emphasis markers must hug the text, so surrounding whitespace is kept
+    // outside of them.
+    let text = plain_text.trim();
+    if text.is_empty() {
+        return plain_text.to_string();
+    }
+    let leading_whitespace = &plain_text[..plain_text.len() - plain_text.trim_start().len()];
+    let trailing_whitespace = &plain_text[plain_text.trim_end().len()..];
+
+    let annotations = &segment.annotations;
+    let mut markdown = text.to_string();
+    if annotations.code {
+        markdown = format!("`{}`", markdown);
+    }
+    if annotations.bold {
+        markdown = format!("**{}**", markdown);
+    }
+    if annotations.italic {
+        markdown = format!("_{}_", markdown);
+    }
+    if annotations.strikethrough {
+        markdown = format!("~~{}~~", markdown);
+    }
+
+    let text_link = match &segment.kind {
+        RichTextKind::Text{ text } => text.link.as_ref().map(|x| x.url.as_str()),
+        _ => None
+    };
+    if let Some(link) = segment.href.as_deref().or(text_link) {
+        markdown = format!("[{}]({})", markdown, link);
+    }
+
+    return format!("{}{}{}", leading_whitespace, markdown, trailing_whitespace);
+}
+
+/// Block types whose children are rendered at the same level as the block itself.
+fn is_container_block(block_type: &BlockType) -> bool{
+    return matches!(
+        block_type,
+        BlockType::ColumnList{ .. } | BlockType::Column{ .. } | BlockType::SyncedBlock{ .. } | BlockType::Template{ .. }
+        | BlockType::Heading1{ .. } | BlockType::Heading2{ .. } | BlockType::Heading3{ .. }
+    );
+}
+
+/// Block types rendered as Markdown block quotes, whose children are quoted along with them.
+fn is_quote_block(block_type: &BlockType) -> bool{
+    return matches!(block_type, BlockType::Quote{ .. } | BlockType::Callout{ .. });
+}
+
+/// Block types rendered as Markdown list items, which are kept on consecutive lines.
+fn is_list_block(block_type: &BlockType) -> bool{
+    return matches!(
+        block_type,
+        BlockType::BulletedListItem{ .. } | BlockType::NumberedListItem{ .. } | BlockType::ToDo{ .. } | BlockType::Toggle{ .. }
+    );
+}
+
+fn render_blocks(nodes: &[BlockNode], depth: usize, output: &mut String, mut block_spans: Option<&mut Vec<Range<usize>>>){
+    let indent = "  ".repeat(depth);
+    let mut numbered_list_counter: usize = 0;
+    let mut previous_was_list_item = false;
+
+    for node in nodes{
+        let block_type = &node.block.block_type;
+        let output_start = output.len();
+
+        // Notion restarts numbering whenever a numbered list is interrupted.
+        if let BlockType::NumberedListItem{ .. } = block_type {
+            numbered_list_counter += 1;
+        }
+        else{
+            numbered_list_counter = 0;
+        }
+
+        let is_list_item = is_list_block(block_type);
+        let mut rendered_block = render_block(node, numbered_list_counter);
+
+        let quotes_children = is_quote_block(block_type) && !node.children.is_empty();
+        if quotes_children {
+            let quoted_children = prefix_lines(&blocks_to_markdown(&node.children), "> ");
+            rendered_block = match rendered_block.is_empty() {
+                true => quoted_children,
+                false => format!("{}\n{}", rendered_block, quoted_children)
+            };
+        }
+
+        if !rendered_block.is_empty() {
+            let keep_together = depth > 0 || (is_list_item && previous_was_list_item);
+            if !output.is_empty() && !keep_together && !output.ends_with("\n\n") {
+                output.push('\n');
+            }
+
+            for line in rendered_block.lines(){
+                if !line.is_empty() {
+                    output.push_str(&indent);
+                    output.push_str(line);
+                }
+                output.push('\n');
+            }
+        }
+        previous_was_list_item = is_list_item;
+
+        // Table rows are rendered as part of the table itself, and quoted children as part
+        // of the quote.
+        if !quotes_children && !matches!(block_type, BlockType::Table{ .. }) {
+            let child_depth = match is_container_block(block_type) {
+                true => depth,
+                false => depth + 1
+            };
+            render_blocks(&node.children, child_depth, output, None);
+        }
+
+        if let Some(block_spans) = block_spans.as_mut() {
+            // The blank line separating the block from the previous one is not part of it.
+            let separator_len = output[output_start..].len() - output[output_start..].trim_start_matches('\n').len();
+            block_spans.push(output_start + separator_len..output.len());
+        }
+    }
+}
+
+fn render_block(node: &BlockNode, numbered_list_counter: usize) -> String{
+    let block = &node.block;
+
+    match &block.block_type{
+        BlockType::Paragraph{ paragraph } => rich_text_to_markdown(&paragraph.rich_text),
+        BlockType::Heading1{ heading_1 } => format!("# {}", rich_text_to_markdown(&heading_1.rich_text)),
+        BlockType::Heading2{ heading_2 } => format!("## {}", rich_text_to_markdown(&heading_2.rich_text)),
+        BlockType::Heading3{ heading_3 } => format!("### {}", rich_text_to_markdown(&heading_3.rich_text)),
+        BlockType::BulletedListItem{ bulleted_list_item: x } | BlockType::Toggle{ toggle: x } => format!("- {}", rich_text_to_markdown(&x.rich_text)),
+        BlockType::NumberedListItem{ numbered_list_item } => format!("{}. {}", numbered_list_counter, rich_text_to_markdown(&numbered_list_item.rich_text)),
+        BlockType::ToDo{ to_do } => {
+            let checkbox = match to_do.checked {
+                true => "[x]",
+                false => "[ ]"
+            };
+            format!("- {} {}", checkbox, rich_text_to_markdown(&to_do.rich_text))
+        },
+        BlockType::Quote{ quote } => prefix_lines(&rich_text_to_markdown(&quote.rich_text), "> "),
+        BlockType::Callout{ callout } => {
+            let text = rich_text_to_markdown(&callout.rich_text);
+            let callout_text = match &callout.icon {
+                Some(Icon::Emoji{ emoji }) => format!("{} {}", emoji, text),
+                _ => text
+            };
+            prefix_lines(&callout_text, "> ")
+        },
+        BlockType::Code{ code } => format!("```{}\n{}\n```", code.language, plain_text(&code.rich_text)),
+        BlockType::Divider{ .. } => String::from("---"),
+        BlockType::Equation{ equation } => format!("$$\n{}\n$$", equation.expression),
+        BlockType::Table{ .. } => render_table(node),
+        BlockType::Bookmark{ bookmark: x } | BlockType::Embed{ embed: x } | BlockType::LinkPreview{ link_preview: x } => {
+            let caption = rich_text_to_markdown(&x.caption);
+            let label = if caption.is_empty() { x.url.clone() } else { caption };
+            format!("[{}]({})", label, x.url)
+        },
+        BlockType::Image{ image } => match image.file.url() {
+            Some(url) if !url.is_empty() => format!("!{}", render_file_link(image, "image")),
+            _ => render_file_link(image, "image")
+        },
+        BlockType::File{ file } => render_file_link(file, "file"),
+        BlockType::Pdf{ pdf } => render_file_link(pdf, "pdf"),
+        BlockType::Video{ video } => render_file_link(video, "video"),
+        BlockType::Audio{ audio } => render_file_link(audio, "audio"),
+        BlockType::ChildPage{ child_page } => format!("[{}]({})", child_page.title, notion_url(&block.id)),
+        BlockType::ChildDatabase{ child_database } => format!("[{} (database)]({})", child_database.title, notion_url(&block.id)),
+        BlockType::LinkToPage{ link_to_page } => {
+            let linked_id = link_to_page.page_id.as_deref().or(link_to_page.database_id.as_deref()).unwrap_or_default();
+            format!("[{}]({})", linked_id, notion_url(linked_id))
+        },
+        // Layout-only blocks carry no text of their own.
+        BlockType::ColumnList{ .. } | BlockType::Column{ .. } | BlockType::SyncedBlock{ .. } | BlockType::Template{ .. }
+        | BlockType::Breadcrumb{ .. } | BlockType::TableOfContents{ .. } | BlockType::TableRow{ .. } | BlockType::Unknown => String::new()
+    }
+}
+
+/// Renders a file as a link, or just its label when Notion gives no URL for it, as for
+/// `file_upload` files.
+fn render_file_link(file_block: &FileBlock, default_label: &str) -> String{
+    let caption = rich_text_to_markdown(&file_block.caption);
+    let label = match (caption.is_empty(), &file_block.name) {
+        (false, _) => caption,
+        (true, Some(name)) => name.clone(),
+        (true, None) => default_label.to_string()
+    };
+    match file_block.file.url() {
+        Some(url) if !url.is_empty() => return format!("[{}]({})", label, url),
+        _ => return label
+    }
+}
+
+fn render_table(node: &BlockNode) -> String{
+    let rows: Vec<Vec<String>> = node.children.iter().map(|row| {
+        match &row.block.block_type {
+            BlockType::TableRow{ table_row } => table_row.cells.iter().map(|cell| rich_text_to_markdown(cell).replace('|', "\\|")).collect(),
+            _ => Vec::new()
+        }
+    }).collect();
+
+    let column_count = rows.iter().map(|x| x.len()).max().unwrap_or_default();
+    if column_count == 0 {
+        return String::new();
+    }
+
+    let mut table_lines: Vec<String> = Vec::with_capacity(rows.len() + 1);
+    for (row_idx, row) in rows.iter().enumerate(){
+        let mut cells = row.clone();
+        cells.resize(column_count, String::new());
+        table_lines.push(format!("| {} |", cells.join(" | ")));
+
+        // Markdown tables always need a header, so the first row doubles as one.
+        if row_idx == 0 {
+            table_lines.push(format!("|{}", " --- |".repeat(column_count)));
+        }
+    }
+
+    return table_lines.join("\n");
+}
+
+/// Prefixes every line of `text`, without leaving trailing whitespace on empty lines.
+fn prefix_lines(text: &str, prefix: &str) -> String{
+    return text.lines().map(|line| {
+        match line.is_empty() {
+            true => prefix.trim_end().to_string(),
+            false => format!("{}{}", prefix, line)
+        }
+    }).collect::<Vec<String>>().join("\n");
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::notion_types::Block;
+    use serde_json::json;
+
+    fn text_segment(text: &str) -> serde_json::Value{
+        return json!({ "type": "text", "plain_text": text, "text": { "content": text } });
+    }
+
+    /// A block of `block_type` whose type-specific object is `content`.
+    fn node(block_type: &str, content: serde_json::Value, children: Vec<BlockNode>) -> BlockNode{
+        let mut block_json = json!({ "id": "block-id", "type": block_type, "has_children": !children.is_empty() });
+        block_json[block_type] = content;
+        let block: Block = serde_json::from_value(block_json).unwrap();
+        return BlockNode{ block, children };
+    }
+
+    fn text_node(block_type: &str, text: &str, children: Vec<BlockNode>) -> BlockNode{
+        return node(block_type, json!({ "rich_text": [text_segment(text)] }), children);
+    }
+
+    #[test]
+    fn annotations_hug_the_text_and_keep_surrounding_whitespace(){
+        let rich_text: Vec<RichText> = serde_json::from_value(json!([
+            { "type": "text", "plain_text": "Mix ", "text": { "content": "Mix " } },
+            { "type": "text", "plain_text": " well ", "text": { "content": " well " }, "annotations": { "bold": true, "italic": true } },
+            { "type": "text", "plain_text": "rm -rf", "text": { "content": "rm -rf" }, "annotations": { "code": true, "strikethrough": true } },
+            { "type": "equation", "plain_text": "x^2", "equation": { "expression": "x^2" } }
+        ])).unwrap();
+
+        assert_eq!(rich_text_to_markdown(&rich_text), "Mix  _**well**_ ~~`rm -rf`~~$x^2$");
+    }
+
+    #[test]
+    fn links_come_from_href_or_the_text_link(){
+        let rich_text: Vec<RichText> = serde_json::from_value(json!([
+            { "type": "text", "plain_text": "docs", "href": "https://example.com/docs", "text": { "content": "docs" } },
+            { "type": "text", "plain_text": " and ", "text": { "content": " and " } },
+            { "type": "text", "plain_text": "blog", "text": { "content": "blog", "link": { "url": "https://example.com/blog" } }, "annotations": { "bold": true } }
+        ])).unwrap();
+
+        assert_eq!(rich_text_to_markdown(&rich_text), "[docs](https://example.com/docs) and [**blog**](https://example.com/blog)");
+    }
+
+    #[test]
+    fn numbered_lists_restart_after_an_interruption(){
+        let nodes = vec![
+            text_node("numbered_list_item", "one", Vec::new()),
+            text_node("numbered_list_item", "two", Vec::new()),
+            text_node("paragraph", "break", Vec::new()),
+            text_node("numbered_list_item", "again", Vec::new())
+        ];
+
+        assert_eq!(blocks_to_markdown(&nodes), "1. one\n2. two\n\nbreak\n\n1. again\n");
+    }
+
+    #[test]
+    fn nested_list_items_are_indented(){
+        let nodes = vec![
+            text_node("bulleted_list_item", "fruit", vec![
+                text_node("numbered_list_item", "apple", Vec::new()),
+                text_node("numbered_list_item", "pear", vec![text_node("to_do", "buy", Vec::new())])
+            ]),
+            text_node("bulleted_list_item", "vegetables", Vec::new())
+        ];
+
+        assert_eq!(blocks_to_markdown(&nodes), "- fruit\n  1. apple\n  2. pear\n    - [ ] buy\n- vegetables\n");
+    }
+
+    #[test]
+    fn children_of_quotes_stay_inside_the_quote(){
+        let nodes = vec![
+            text_node("quote", "Said someone", vec![
+                text_node("paragraph", "first", Vec::new()),
+                text_node("bulleted_list_item", "point", Vec::new())
+            ]),
+            text_node("paragraph", "after", Vec::new())
+        ];
+
+        assert_eq!(blocks_to_markdown(&nodes), "> Said someone\n> first\n>\n> - point\n\nafter\n");
+    }
+
+    #[test]
+    fn code_blocks_are_fenced_with_their_language(){
+        let nodes = vec![node("code", json!({
+            "rich_text": [text_segment("fn main(){\n    println!(\"hi\");\n}")],
+            "language": "rust"
+        }), Vec::new())];
+
+        assert_eq!(blocks_to_markdown(&nodes), "```rust\nfn main(){\n    println!(\"hi\");\n}\n```\n");
+    }
+
+    #[test]
+    fn unknown_blocks_render_nothing(){
+        let nodes = vec![
+            text_node("paragraph", "before", Vec::new()),
+            node("ai_block", json!({ "prompt": "summarize" }), Vec::new()),
+            text_node("paragraph", "after", Vec::new())
+        ];
+
+        assert_eq!(blocks_to_markdown(&nodes), "before\n\nafter\n");
+    }
+
+    #[test]
+    fn files_without_a_url_render_only_their_name(){
+        let nodes = vec![
+            node("file", json!({ "type": "file_upload", "file_upload": { "id": "upload-id" }, "name": "report.pdf" }), Vec::new()),
+            node("image", json!({ "type": "external", "external": { "url": "https://example.com/cat.png" } }), Vec::new())
+        ];
+
+        assert_eq!(blocks_to_markdown(&nodes), "report.pdf\n\n![image](https://example.com/cat.png)\n");
+    }
+}
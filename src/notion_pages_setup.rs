@@ -1,149 +1,741 @@
+use futures::future::{BoxFuture, Future};
+use futures::stream::{self, Stream, TryStreamExt};
+use reqwest::header::HeaderValue;
 use reqwest::Client;
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::notion_error::NotionError;
+use crate::notion_markdown;
+use crate::notion_rate_limit::{RateLimiter, RetryPolicy, DEFAULT_BURST_SIZE, DEFAULT_REQUESTS_PER_SECOND, MIN_REQUESTS_PER_SECOND};
+use crate::notion_types::{Block, BlockType, Database, Page, PaginatedList, SearchResult};
 
+/// Maximum page size accepted by the Notion API for paginated endpoints.
+const NOTION_PAGE_SIZE: u32 = 100;
+
+/// Default depth up to which nested blocks are fetched when rendering a page.
+pub const DEFAULT_MAX_BLOCK_DEPTH: usize = 8;
+
+/// A block together with its (recursively fetched) children.
+#[derive(Debug, Clone)]
+pub struct BlockNode{
+    pub block: Block,
+    pub children: Vec<BlockNode>
+}
+
+/// Default Notion API endpoint.
+pub const DEFAULT_NOTION_BASE_URL: &str = "https://api.notion.com";
+
+/// Notion-Version header sent when none is configured.
+pub const DEFAULT_NOTION_VERSION: &str = "2022-06-28";
+
+/// Environment variable `NotionPagesAPI::new` reads the integration token from.
+pub const NOTION_API_KEY_ENV: &str = "NOTION_API_KEY";
+
+/// Restricts `NotionPagesAPI::search` to pages or databases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchObjectType{
+    Page,
+    Database
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection{
+    Ascending,
+    Descending
+}
+
+/// Parameters of `NotionPagesAPI::search`. An empty query matches everything shared with
+/// the integration.
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery{
+    pub query: Option<String>,
+    pub object_type: Option<SearchObjectType>,
+    /// Sorts results by `last_edited_time` in the given direction.
+    pub sort_by_last_edited: Option<SortDirection>
+}
+
+impl SearchQuery{
+    pub fn new() -> Self{
+        return Self::default();
+    }
+
+    pub fn query(mut self, query: impl Into<String>) -> Self{
+        self.query = Some(query.into());
+        return self;
+    }
+
+    pub fn object_type(mut self, object_type: SearchObjectType) -> Self{
+        self.object_type = Some(object_type);
+        return self;
+    }
+
+    pub fn sort_by_last_edited(mut self, sort_direction: SortDirection) -> Self{
+        self.sort_by_last_edited = Some(sort_direction);
+        return self;
+    }
+
+    fn to_request_body(&self, start_cursor: Option<&str>) -> serde_json::Value{
+        let mut request_body = serde_json::json!({ "page_size": NOTION_PAGE_SIZE });
+
+        if let Some(query) = &self.query {
+            request_body["query"] = serde_json::json!(query);
+        }
+        if let Some(object_type) = self.object_type {
+            let object_value = match object_type {
+                SearchObjectType::Page => "page",
+                SearchObjectType::Database => "database"
+            };
+            request_body["filter"] = serde_json::json!({ "property": "object", "value": object_value });
+        }
+        if let Some(sort_direction) = self.sort_by_last_edited {
+            let direction = match sort_direction {
+                SortDirection::Ascending => "ascending",
+                SortDirection::Descending => "descending"
+            };
+            request_body["sort"] = serde_json::json!({ "direction": direction, "timestamp": "last_edited_time" });
+        }
+        if let Some(start_cursor) = start_cursor {
+            request_body["start_cursor"] = serde_json::json!(start_cursor);
+        }
+
+        return request_body;
+    }
+}
+
+/// Parameters of `NotionPagesAPI::query_database`, passed through to Notion as-is.
+///
+/// See https://developers.notion.com/reference/post-database-query-filter for the shape
+/// of `filter` and `sorts`, e.g. `{"property": "Cuisine", "select": {"equals": "Thai"}}`.
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseQuery{
+    pub filter: Option<serde_json::Value>,
+    pub sorts: Option<serde_json::Value>
+}
+
+impl DatabaseQuery{
+    pub fn new() -> Self{
+        return Self::default();
+    }
+
+    pub fn filter(mut self, filter: serde_json::Value) -> Self{
+        self.filter = Some(filter);
+        return self;
+    }
+
+    pub fn sorts(mut self, sorts: serde_json::Value) -> Self{
+        self.sorts = Some(sorts);
+        return self;
+    }
+
+    fn to_request_body(&self, start_cursor: Option<&str>) -> serde_json::Value{
+        let mut request_body = serde_json::json!({ "page_size": NOTION_PAGE_SIZE });
+
+        if let Some(filter) = &self.filter {
+            request_body["filter"] = filter.clone();
+        }
+        if let Some(sorts) = &self.sorts {
+            request_body["sorts"] = sorts.clone();
+        }
+        if let Some(start_cursor) = start_cursor {
+            request_body["start_cursor"] = serde_json::json!(start_cursor);
+        }
+
+        return request_body;
+    }
+}
+
+/// Notion API client. Every method takes `&self`, so one client can serve concurrent
+/// requests; clones share the connection pool and the rate limiter.
+#[derive(Clone)]
 pub struct NotionPagesAPI{
     auth_headers: reqwest::header::HeaderMap,
-    request_client: Client
+    request_client: Client,
+    base_url: String,
+    max_block_depth: usize,
+    rate_limiter: Arc<RateLimiter>,
+    retry_policy: RetryPolicy
+}
+
+/// Turns a function fetching one page of results for a cursor into a stream over every
+/// result, following `next_cursor` until no more pages are available.
+fn paginate<'a, T, F, Fut>(fetch_page: F) -> impl Stream<Item = Result<T, NotionError>> + 'a
+where
+    T: 'a,
+    F: Fn(Option<String>) -> Fut + 'a,
+    Fut: Future<Output = Result<(Vec<T>, Option<String>), NotionError>> + 'a
+{
+    // `None` marks the end of pagination, `Some(None)` the first request.
+    let initial_state: Option<Option<String>> = Some(None);
+
+    return stream::try_unfold(initial_state, move |state| {
+        let next_page = state.map(&fetch_page);
+        async move {
+            let (results, next_cursor) = match next_page {
+                Some(next_page) => next_page.await?,
+                None => return Ok::<_, NotionError>(None)
+            };
+
+            return Ok(Some((stream::iter(results.into_iter().map(Ok)), next_cursor.map(Some))));
+        }
+    })
+    .try_flatten();
 }
 
 pub fn print_type_of<T>(_: &T) {
     println!("{}", std::any::type_name::<T>())
 }
 
+/// Builder for `NotionPagesAPI`, created through `NotionPagesAPI::builder`.
+#[derive(Debug, Clone)]
+pub struct NotionPagesAPIBuilder{
+    token: Option<String>,
+    base_url: String,
+    notion_version: String,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    proxy: Option<String>,
+    user_agent: Option<String>,
+    max_block_depth: usize,
+    requests_per_second: f64,
+    burst_size: u32,
+    retry_policy: RetryPolicy
+}
 
-impl NotionPagesAPI{
-    pub fn new() -> Self{
+impl Default for NotionPagesAPIBuilder{
+    fn default() -> Self{
+        return NotionPagesAPIBuilder{
+            token: None,
+            base_url: DEFAULT_NOTION_BASE_URL.to_string(),
+            notion_version: DEFAULT_NOTION_VERSION.to_string(),
+            timeout: None,
+            connect_timeout: None,
+            proxy: None,
+            user_agent: None,
+            max_block_depth: DEFAULT_MAX_BLOCK_DEPTH,
+            requests_per_second: DEFAULT_REQUESTS_PER_SECOND,
+            burst_size: DEFAULT_BURST_SIZE,
+            retry_policy: RetryPolicy::default()
+        };
+    }
+}
+
+impl NotionPagesAPIBuilder{
+    /// Integration token sent as `Authorization: Bearer <token>`. Required.
+    pub fn token(mut self, token: impl Into<String>) -> Self{
+        self.token = Some(token.into());
+        return self;
+    }
 
-        let client = reqwest::Client::new();
+    /// Overrides `https://api.notion.com`, e.g. to point the client at a mock server.
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self{
+        self.base_url = base_url.into();
+        return self;
+    }
 
-        let auth_headers = Self::setup_auth_headers();
+    pub fn notion_version(mut self, notion_version: impl Into<String>) -> Self{
+        self.notion_version = notion_version.into();
+        return self;
+    }
 
-        return NotionPagesAPI{
-            auth_headers: auth_headers,
-            request_client: client
-        };
+    /// Timeout for a whole request, from connecting until the body has been read.
+    pub fn timeout(mut self, timeout: Duration) -> Self{
+        self.timeout = Some(timeout);
+        return self;
     }
 
-    fn setup_auth_headers() -> reqwest::header::HeaderMap{
-        println!("Setting up auth headers");
-        let notion_api_key = std::env::var("NOTION_API_KEY").expect("Notion API Key not set in environment");
-        let mut headers = reqwest::header::HeaderMap::new();
-        headers.insert(reqwest::header::CONTENT_TYPE, "application/json".parse().unwrap());
-        headers.insert(reqwest::header::AUTHORIZATION, format!("Bearer {}", notion_api_key).parse().unwrap());
-        headers.insert(reqwest::header::HeaderName::from_static("notion-version"), "2022-06-28".parse().unwrap());
-        println!("Done setting up auth headers");
-        return headers;
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self{
+        self.connect_timeout = Some(connect_timeout);
+        return self;
     }
 
-    fn str_replacer(string_to_replace: &str) -> String{
-        match string_to_replace{
-            "heading_1" => String::from("#"),
-            "heading_2" => String::from("##"),
-            "bulleted_list_item" => String::from("-"),
-            _ => String::from(string_to_replace)
+    /// Routes every request through the proxy at `proxy_url`.
+    pub fn proxy(mut self, proxy_url: impl Into<String>) -> Self{
+        self.proxy = Some(proxy_url.into());
+        return self;
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self{
+        self.user_agent = Some(user_agent.into());
+        return self;
+    }
+
+    /// How deep `get_page_content` descends into nested blocks. A depth of `0` only
+    /// renders the top-level blocks of a page.
+    pub fn max_block_depth(mut self, max_block_depth: usize) -> Self{
+        self.max_block_depth = max_block_depth;
+        return self;
+    }
+
+    /// Limits the client to `requests_per_second` on average, allowing bursts of up to
+    /// `burst_size` requests. `build` rejects rates below `MIN_REQUESTS_PER_SECOND`.
+    pub fn rate_limit(mut self, requests_per_second: f64, burst_size: u32) -> Self{
+        self.requests_per_second = requests_per_second;
+        self.burst_size = burst_size;
+        return self;
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self{
+        self.retry_policy = retry_policy;
+        return self;
+    }
+
+    pub fn build(self) -> Result<NotionPagesAPI, NotionError>{
+        let token = match self.token {
+            Some(token) if !token.trim().is_empty() => token,
+            _ => return Err(NotionError::Config("Notion API token not set".to_string()))
+        };
+        if !self.requests_per_second.is_finite() || self.requests_per_second < MIN_REQUESTS_PER_SECOND {
+            return Err(NotionError::Config(format!("Rate limit must be at least {} requests per second, got {}", MIN_REQUESTS_PER_SECOND, self.requests_per_second)));
+        }
+
+        let mut auth_headers = reqwest::header::HeaderMap::new();
+        auth_headers.insert(reqwest::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        auth_headers.insert(
+            reqwest::header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token)).map_err(|_| NotionError::Config("Notion API token is not a valid header value".to_string()))?
+        );
+        auth_headers.insert(
+            reqwest::header::HeaderName::from_static("notion-version"),
+            HeaderValue::from_str(&self.notion_version).map_err(|_| NotionError::Config(format!("Invalid Notion-Version {:?}", self.notion_version)))?
+        );
+
+        let mut client_builder = Client::builder();
+        if let Some(timeout) = self.timeout {
+            client_builder = client_builder.timeout(timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            client_builder = client_builder.connect_timeout(connect_timeout);
+        }
+        if let Some(proxy_url) = &self.proxy {
+            let proxy = reqwest::Proxy::all(proxy_url).map_err(|err| NotionError::Config(format!("Invalid proxy {:?}: {}", proxy_url, err)))?;
+            client_builder = client_builder.proxy(proxy);
         }
+        if let Some(user_agent) = &self.user_agent {
+            client_builder = client_builder.user_agent(user_agent);
+        }
+
+        let request_client = client_builder.build().map_err(|err| NotionError::Config(format!("Failed to build the HTTP client: {}", err)))?;
+
+        return Ok(NotionPagesAPI{
+            auth_headers,
+            request_client,
+            base_url: self.base_url.trim_end_matches('/').to_string(),
+            max_block_depth: self.max_block_depth,
+            rate_limiter: Arc::new(RateLimiter::new(self.requests_per_second, self.burst_size)),
+            retry_policy: self.retry_policy
+        });
     }
+}
 
-    pub async fn get_children(&mut self, page_id: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error>>{
-        println!("Making async call to get Children for page {}", page_id);
-        
-        let response_url: String = format!("https://api.notion.com/v1/blocks/{}/children?page_size=100", page_id);
-
-        let response = self.request_client.get(response_url)
-        .headers(self.auth_headers.clone())
-        .send();
-
-
-        match response.await {
-            Ok(response) => {
-                if response.status().is_success(){
-                    let response_body = response.text();
-                    
-                    match response_body.await{
-                        Ok(response_body) => {
-                            let json_body: serde_json::Value = serde_json::from_str(&response_body).expect("Failed to parse JSON");
-                            let results = &json_body["results"];
-                            let child_pages: HashMap<String, String> = results.as_array().unwrap().iter().filter_map(|x| {
-                                if x["has_children"].as_bool() == Some(true) {
-                                    return Some(
-                                        (
-                                            x["child_page"]["title"].as_str().unwrap().to_string(),
-                                            x["id"].as_str().unwrap().to_string()
-                                        )
-                                    );
-                                }
-                                else{
-                                    return None;
-                                }
-                            }).collect();
-
-                            return Ok(child_pages);
-                        }
-                        Err(err) => {
-                            println!("Errored due to err {}", err);
-                            return Err(Box::new(err));
-                        }
-                    }
+impl NotionPagesAPI{
+    /// Creates a client with default settings, reading the token from `NOTION_API_KEY`.
+    pub fn new() -> Result<Self, NotionError>{
+        let notion_api_key = std::env::var(NOTION_API_KEY_ENV)
+            .map_err(|_| NotionError::Config(format!("{} not set in environment", NOTION_API_KEY_ENV)))?;
+
+        return Self::builder().token(notion_api_key).build();
+    }
+
+    pub fn builder() -> NotionPagesAPIBuilder{
+        return NotionPagesAPIBuilder::default();
+    }
+
+    /// Sends `request` through the rate limiter, retrying according to the retry policy,
+    /// and decodes a successful response body into `T`.
+    async fn execute_request<T: DeserializeOwned>(&self, request: reqwest::RequestBuilder) -> Result<T, NotionError>{
+        let mut attempt: u32 = 0;
+
+        loop {
+            // Requests with streaming bodies cannot be replayed, so they are sent only once.
+            let attempt_request = match request.try_clone() {
+                Some(attempt_request) => attempt_request,
+                None => {
+                    self.rate_limiter.acquire().await;
+                    return self.send_request(request).await;
                 }
-                else{
-                    return Err(format!("Received unsuccessful response status: {:?}", response.status()).into())
+            };
+
+            self.rate_limiter.acquire().await;
+            match self.send_request(attempt_request).await {
+                Ok(response_body) => return Ok(response_body),
+                Err(err) => {
+                    let retry_delay = match self.retry_policy.retry_delay(attempt, &err) {
+                        Some(retry_delay) => retry_delay,
+                        None => return Err(err)
+                    };
+
+                    log::warn!("Notion request failed ({}), retrying in {:?} (attempt {} of {})", err, retry_delay, attempt + 1, self.retry_policy.max_retries);
+                    tokio::time::sleep(retry_delay).await;
+                    attempt += 1;
                 }
             }
-            Err(err) => {
-                println!("Errored due to the error {:?}", err);
-                return Err(Box::new(err));
+        }
+    }
+
+    /// Sends `request` once with the auth headers attached and decodes a successful
+    /// response body into `T`. Non-2xx responses are mapped onto the matching `NotionError`.
+    async fn send_request<T: DeserializeOwned>(&self, request: reqwest::RequestBuilder) -> Result<T, NotionError>{
+        let response = request.headers(self.auth_headers.clone()).send().await?;
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let response_body = response.text().await?;
+
+        if !status.is_success() {
+            log::warn!("Notion request failed with status {}", status);
+            return Err(NotionError::from_response(status, &headers, &response_body));
+        }
+
+        return Ok(serde_json::from_str(&response_body)?);
+    }
+
+    /// Fetches a single page of children for `block_id`, starting at `start_cursor`.
+    ///
+    /// Returns the blocks of that page along with the cursor of the next page, which is
+    /// `None` once Notion reports `has_more == false`.
+    pub async fn get_block_children_page(&self, block_id: &str, start_cursor: Option<&str>) -> Result<(Vec<Block>, Option<String>), NotionError>{
+        let response_url: String = format!("{}/v1/blocks/{}/children", self.base_url, block_id);
+        let mut request = self.request_client.get(response_url).query(&[("page_size", NOTION_PAGE_SIZE)]);
+        if let Some(start_cursor) = start_cursor {
+            request = request.query(&[("start_cursor", start_cursor)]);
+        }
+
+        let block_list: PaginatedList<Block> = self.execute_request(request).await?;
+
+        return Ok(block_list.into_page());
+    }
+
+    /// Streams every child block of `block_id`, following `next_cursor` until Notion
+    /// reports that no more pages are available.
+    pub fn block_children_stream<'a>(&'a self, block_id: &'a str) -> impl Stream<Item = Result<Block, NotionError>> + 'a{
+        return paginate(move |start_cursor| async move {
+            return self.get_block_children_page(block_id, start_cursor.as_deref()).await;
+        });
+    }
+
+    /// Collects every child block of `block_id` across all pages.
+    pub async fn get_all_block_children(&self, block_id: &str) -> Result<Vec<Block>, NotionError>{
+        return self.block_children_stream(block_id).try_collect().await;
+    }
+
+    /// Fetches a single page of `POST /v1/search` results, starting at `start_cursor`.
+    pub async fn search_page(&self, search_query: &SearchQuery, start_cursor: Option<&str>) -> Result<(Vec<SearchResult>, Option<String>), NotionError>{
+        let response_url: String = format!("{}/v1/search", self.base_url);
+        let request_body = search_query.to_request_body(start_cursor);
+
+        let search_results: PaginatedList<SearchResult> = self.execute_request(self.request_client.post(response_url).json(&request_body)).await?;
+
+        return Ok(search_results.into_page());
+    }
+
+    /// Streams every page and database shared with the integration that matches
+    /// `search_query`, following `next_cursor` across result pages.
+    pub fn search_stream<'a>(&'a self, search_query: &'a SearchQuery) -> impl Stream<Item = Result<SearchResult, NotionError>> + 'a{
+        return paginate(move |start_cursor| async move {
+            return self.search_page(search_query, start_cursor.as_deref()).await;
+        });
+    }
+
+    /// Collects every search result matching `search_query` across all pages.
+    pub async fn search(&self, search_query: &SearchQuery) -> Result<Vec<SearchResult>, NotionError>{
+        log::info!("Searching Notion with {:?}", search_query);
+        return self.search_stream(search_query).try_collect().await;
+    }
+
+    /// Retrieves a page's properties and metadata (`GET /v1/pages/{id}`), not its content.
+    pub async fn retrieve_page(&self, page_id: &str) -> Result<Page, NotionError>{
+        let response_url: String = format!("{}/v1/pages/{}", self.base_url, page_id);
+        return self.execute_request(self.request_client.get(response_url)).await;
+    }
+
+    pub async fn retrieve_database(&self, database_id: &str) -> Result<Database, NotionError>{
+        let response_url: String = format!("{}/v1/databases/{}", self.base_url, database_id);
+        return self.execute_request(self.request_client.get(response_url)).await;
+    }
+
+    /// Fetches a single page of rows of `database_id` matching `database_query`.
+    pub async fn query_database_page(&self, database_id: &str, database_query: &DatabaseQuery, start_cursor: Option<&str>) -> Result<(Vec<Page>, Option<String>), NotionError>{
+        let response_url: String = format!("{}/v1/databases/{}/query", self.base_url, database_id);
+        let request_body = database_query.to_request_body(start_cursor);
+
+        let database_rows: PaginatedList<Page> = self.execute_request(self.request_client.post(response_url).json(&request_body)).await?;
+
+        return Ok(database_rows.into_page());
+    }
+
+    /// Streams every row of `database_id` matching `database_query`.
+    pub fn query_database_stream<'a>(&'a self, database_id: &'a str, database_query: &'a DatabaseQuery) -> impl Stream<Item = Result<Page, NotionError>> + 'a{
+        return paginate(move |start_cursor| async move {
+            return self.query_database_page(database_id, database_query, start_cursor.as_deref()).await;
+        });
+    }
+
+    /// Collects every row of `database_id` matching `database_query` across all pages.
+    pub async fn query_database(&self, database_id: &str, database_query: &DatabaseQuery) -> Result<Vec<Page>, NotionError>{
+        log::info!("Querying Notion database {}", database_id);
+        return self.query_database_stream(database_id, database_query).try_collect().await;
+    }
+
+    /// Fetches the children of `block_id` and recursively descends into every block with
+    /// `has_children == true`, up to `max_depth` levels below the top-level blocks.
+    ///
+    /// Child pages and child databases are not descended into since they are separate
+    /// pages with their own content.
+    pub fn get_block_tree<'a>(&'a self, block_id: &'a str, max_depth: usize) -> BoxFuture<'a, Result<Vec<BlockNode>, NotionError>>{
+        return Box::pin(async move {
+            let blocks = self.get_all_block_children(block_id).await?;
+            let mut nodes: Vec<BlockNode> = Vec::with_capacity(blocks.len());
+
+            for block in blocks{
+                let mut children = Vec::new();
+                if max_depth > 0 && Self::should_descend(&block) {
+                    children = self.get_block_tree(&block.id, max_depth - 1).await?;
+                }
+                nodes.push(BlockNode{ block, children });
             }
+
+            return Ok(nodes);
+        });
+    }
+
+    fn should_descend(block: &Block) -> bool{
+        if !block.has_children {
+            return false;
         }
+        return !matches!(block.block_type, BlockType::ChildPage{ .. } | BlockType::ChildDatabase{ .. });
     }
 
-    pub async fn get_page_content(&mut self, page_id: &str) -> Result<String, Box<dyn std::error::Error>>{
-        println!("Making async call to get page contents for page {}", page_id);
-        
-        let response_url: String = format!("https://api.notion.com/v1/blocks/{}/children?page_size=100", page_id); 
-
-        let response = self.request_client.get(response_url)
-        .headers(self.auth_headers.clone())
-        .send();
-
-        match response.await {
-            Ok(response) => {
-                if response.status().is_success(){
-                    let response_body = response.text();
-                    
-                    match response_body.await{
-                        Ok(response_body) => {
-                            let json_body: serde_json::Value = serde_json::from_str(&response_body).expect("Failed to parse JSON");
-                            let results = &json_body["results"];
-
-                            let page_content_str: String = results.as_array().unwrap().iter().map(|x| {
-                                let tmp_type = &x["type"];
-                                let text_value = x[tmp_type.as_str().unwrap()]["rich_text"][0]["plain_text"].as_str().unwrap_or_default().to_string();
-
-                                let result_string = format!(
-                                    "{}{}\n",
-                                    &Self::str_replacer(tmp_type.as_str().unwrap()),
-                                    &text_value
-                                );
-                                return result_string;
-                            }).collect();
-
-                            return Ok(page_content_str);
-                        }
-                        Err(err) => {
-                            println!("Errored due to err {}", err);
-                            return Err(Box::new(err));
-                        }
-                    }
+    /// Returns the direct child pages of `page_id`, keyed by title. Use `NotionCrawler`
+    /// to discover pages nested deeper or inside other blocks.
+    pub async fn get_children(&self, page_id: &str) -> Result<HashMap<String, String>, NotionError>{
+        println!("Making async call to get Children for page {}", page_id);
+
+        let results = self.get_all_block_children(page_id).await?;
+
+        let child_pages: HashMap<String, String> = results.into_iter().filter_map(|x| {
+            match x.block_type {
+                BlockType::ChildPage{ child_page } => {
+                    return Some((child_page.title, x.id));
                 }
-                else{
-                    return Err(format!("Received unsuccessful response status: {:?}", response.status()).into())
+                _ => {
+                    return None;
                 }
             }
-            Err(err) => {
-                println!("Errored due to the error {:?}", err);
-                return Err(Box::new(err));
-            }
+        }).collect();
+
+        return Ok(child_pages);
+    }
+
+    /// The block tree of a page, fetched up to the configured `max_block_depth`.
+    pub async fn get_page_block_tree(&self, page_id: &str) -> Result<Vec<BlockNode>, NotionError>{
+        return self.get_block_tree(page_id, self.max_block_depth).await;
+    }
+
+    pub async fn get_page_content(&self, page_id: &str) -> Result<String, NotionError>{
+        println!("Making async call to get page contents for page {}", page_id);
+
+        let block_tree = self.get_page_block_tree(page_id).await?;
+
+        let page_content_str = notion_markdown::blocks_to_markdown(&block_tree);
+
+        return Ok(page_content_str);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, method, path, query_param, query_param_is_missing};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// A client talking to `mock_server`, without retries or rate limiting getting in the
+    /// way of the tests.
+    fn mock_notion_api(mock_server: &MockServer) -> NotionPagesAPI{
+        return NotionPagesAPI::builder()
+            .token("secret")
+            .base_url(mock_server.uri())
+            .rate_limit(1000.0, 1000)
+            .retry_policy(RetryPolicy::disabled())
+            .build()
+            .unwrap();
+    }
+
+    fn paragraph_block(block_id: &str, text: &str) -> serde_json::Value{
+        return json!({
+            "object": "block",
+            "id": block_id,
+            "type": "paragraph",
+            "has_children": false,
+            "paragraph": { "rich_text": [{ "type": "text", "plain_text": text, "text": { "content": text } }] }
+        });
+    }
+
+    #[tokio::test]
+    async fn block_children_follow_next_cursor_across_pages(){
+        let mock_server = MockServer::start().await;
+        // Cursors are opaque, so they must reach Notion exactly as they were returned.
+        let next_cursor = "cursor/2&page_size=1";
+
+        Mock::given(method("GET"))
+            .and(path("/v1/blocks/page-1/children"))
+            .and(query_param("page_size", "100"))
+            .and(query_param_is_missing("start_cursor"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "object": "list",
+                "results": [paragraph_block("block-1", "first"), paragraph_block("block-2", "second")],
+                "next_cursor": next_cursor,
+                "has_more": true
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/blocks/page-1/children"))
+            .and(query_param("page_size", "100"))
+            .and(query_param("start_cursor", next_cursor))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "object": "list",
+                "results": [paragraph_block("block-3", "third")],
+                "next_cursor": "ignored-cursor",
+                "has_more": false
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let notion_api = mock_notion_api(&mock_server);
+        let blocks = notion_api.get_all_block_children("page-1").await.unwrap();
+
+        let block_ids: Vec<&str> = blocks.iter().map(|x| x.id.as_str()).collect();
+        assert_eq!(block_ids, vec!["block-1", "block-2", "block-3"]);
+    }
+
+    #[tokio::test]
+    async fn pagination_stops_at_the_first_failing_page(){
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/v1/blocks/page-1/children"))
+            .and(query_param_is_missing("start_cursor"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "results": [paragraph_block("block-1", "first")],
+                "next_cursor": "cursor-2",
+                "has_more": true
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/blocks/page-1/children"))
+            .and(query_param("start_cursor", "cursor-2"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "object": "error",
+                "status": 404,
+                "code": "object_not_found",
+                "message": "Could not find block"
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let notion_api = mock_notion_api(&mock_server);
+        match notion_api.get_all_block_children("page-1").await {
+            Err(NotionError::NotFound{ code, .. }) => assert_eq!(code, "object_not_found"),
+            other => panic!("expected NotFound, got {:?}", other.map(|x| x.len()))
         }
+    }
+
+    #[test]
+    fn rates_that_are_not_positive_and_finite_are_rejected(){
+        for requests_per_second in [0.0, -1.0, f64::NAN, f64::INFINITY, MIN_REQUESTS_PER_SECOND / 2.0]{
+            let build_result = NotionPagesAPI::builder().token("secret").rate_limit(requests_per_second, 3).build();
+            assert!(matches!(build_result, Err(NotionError::Config(_))), "rate {}", requests_per_second);
+        }
+        assert!(NotionPagesAPI::builder().token("secret").rate_limit(MIN_REQUESTS_PER_SECOND, 0).build().is_ok());
+    }
+
+    #[test]
+    fn search_query_serializes_filter_sort_and_cursor(){
+        assert_eq!(SearchQuery::new().to_request_body(None), json!({ "page_size": 100 }));
 
+        let search_query = SearchQuery::new()
+            .query("carbonara")
+            .object_type(SearchObjectType::Database)
+            .sort_by_last_edited(SortDirection::Ascending);
+        assert_eq!(search_query.to_request_body(Some("cursor-2")), json!({
+            "page_size": 100,
+            "query": "carbonara",
+            "filter": { "property": "object", "value": "database" },
+            "sort": { "direction": "ascending", "timestamp": "last_edited_time" },
+            "start_cursor": "cursor-2"
+        }));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn search_pages_through_results_and_keeps_unknown_objects(){
+        let mock_server = MockServer::start().await;
+        let search_query = SearchQuery::new().object_type(SearchObjectType::Page).sort_by_last_edited(SortDirection::Descending);
+
+        Mock::given(method("POST"))
+            .and(path("/v1/search"))
+            .and(body_json(search_query.to_request_body(None)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "object": "list",
+                "results": [{ "object": "page", "id": "page-1", "properties": {} }, { "object": "data_source", "id": "source-1" }],
+                "next_cursor": "cursor-2",
+                "has_more": true
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1/search"))
+            .and(body_json(search_query.to_request_body(Some("cursor-2"))))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "object": "list",
+                "results": [{ "object": "database", "id": "database-1", "title": [] }],
+                "next_cursor": null,
+                "has_more": false
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let notion_api = mock_notion_api(&mock_server);
+        let search_results = notion_api.search(&search_query).await.unwrap();
+
+        assert!(matches!(search_results[..], [SearchResult::Page(_), SearchResult::Unknown, SearchResult::Database(_)]));
+        assert_eq!(search_results[2].id(), Some("database-1"));
+    }
+
+    #[tokio::test]
+    async fn database_queries_pass_filter_and_sorts_through(){
+        let mock_server = MockServer::start().await;
+        let database_query = DatabaseQuery::new()
+            .filter(json!({ "property": "Cuisine", "select": { "equals": "Thai" } }))
+            .sorts(json!([{ "property": "Name", "direction": "ascending" }]));
+
+        Mock::given(method("POST"))
+            .and(path("/v1/databases/database-1/query"))
+            .and(body_json(json!({
+                "page_size": 100,
+                "filter": { "property": "Cuisine", "select": { "equals": "Thai" } },
+                "sorts": [{ "property": "Name", "direction": "ascending" }]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "object": "list",
+                "results": [{ "object": "page", "id": "row-1", "properties": {} }],
+                "has_more": false
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let notion_api = mock_notion_api(&mock_server);
+        let database_rows = notion_api.query_database("database-1", &database_query).await.unwrap();
+
+        let row_ids: Vec<&str> = database_rows.iter().map(|x| x.id.as_str()).collect();
+        assert_eq!(row_ids, vec!["row-1"]);
+    }
+}
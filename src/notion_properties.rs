@@ -0,0 +1,332 @@
+//! Typed Notion page property values and their conversion into Qdrant payload.
+
+use qdrant_client::prelude::Payload;
+use qdrant_client::qdrant::value::Kind;
+use qdrant_client::qdrant::{NullValue, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::notion_types::{plain_text, FileObject, RichText, User};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SelectOption{
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub color: Option<String>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DateValue{
+    pub start: String,
+    #[serde(default)]
+    pub end: Option<String>,
+    #[serde(default)]
+    pub time_zone: Option<String>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RelationReference{
+    pub id: String
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NamedFile{
+    #[serde(default)]
+    pub name: String,
+    #[serde(flatten)]
+    pub file: FileObject
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UniqueId{
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub number: Option<i64>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FormulaValue{
+    String{ string: Option<String> },
+    Number{ number: Option<f64> },
+    Boolean{ boolean: Option<bool> },
+    Date{ date: Option<DateValue> },
+    #[serde(other)]
+    Unknown
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RollupValue{
+    Number{ number: Option<f64> },
+    Date{ date: Option<DateValue> },
+    Array{ array: Vec<PropertyValue> },
+    #[serde(other)]
+    Unknown
+}
+
+/// The value of a single page property, e.g. a database row's `Cuisine` select.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PropertyValue{
+    Title{ title: Vec<RichText> },
+    RichText{ rich_text: Vec<RichText> },
+    Number{ number: Option<f64> },
+    Select{ select: Option<SelectOption> },
+    MultiSelect{ multi_select: Vec<SelectOption> },
+    Status{ status: Option<SelectOption> },
+    Date{ date: Option<DateValue> },
+    Checkbox{ checkbox: bool },
+    People{ people: Vec<User> },
+    Relation{ relation: Vec<RelationReference> },
+    Formula{ formula: FormulaValue },
+    Rollup{ rollup: RollupValue },
+    Url{ url: Option<String> },
+    Email{ email: Option<String> },
+    PhoneNumber{ phone_number: Option<String> },
+    Files{ files: Vec<NamedFile> },
+    CreatedTime{ created_time: String },
+    LastEditedTime{ last_edited_time: String },
+    CreatedBy{ created_by: User },
+    LastEditedBy{ last_edited_by: User },
+    UniqueId{ unique_id: UniqueId },
+    #[serde(other)]
+    Unknown
+}
+
+fn null_value() -> Value{
+    return Value{ kind: Some(Kind::NullValue(NullValue::NullValue.into())) };
+}
+
+fn optional_value<T: Into<Value>>(value: Option<T>) -> Value{
+    match value{
+        Some(value) => value.into(),
+        None => null_value()
+    }
+}
+
+fn user_name(user: &User) -> String{
+    return user.display_name().to_string();
+}
+
+fn date_to_value(date: &Option<DateValue>) -> Value{
+    match date{
+        Some(DateValue{ start, end: None, .. }) => start.clone().into(),
+        Some(DateValue{ start, end: Some(end), .. }) => vec![("start", start.clone()), ("end", end.clone())].into(),
+        None => null_value()
+    }
+}
+
+fn date_to_text(date: &Option<DateValue>) -> String{
+    match date{
+        Some(DateValue{ start, end: None, .. }) => start.clone(),
+        Some(DateValue{ start, end: Some(end), .. }) => format!("{} → {}", start, end),
+        None => String::new()
+    }
+}
+
+impl PropertyValue{
+    /// Converts the property into a Qdrant payload value: text becomes strings, selects
+    /// their option names, people their names, relations the related page IDs, and
+    /// formulas and rollups their computed value. Empty properties become null.
+    pub fn to_payload_value(&self) -> Value{
+        match self{
+            PropertyValue::Title{ title: x } | PropertyValue::RichText{ rich_text: x } => plain_text(x).into(),
+            PropertyValue::Number{ number } => optional_value(*number),
+            PropertyValue::Select{ select: x } | PropertyValue::Status{ status: x } => optional_value(x.as_ref().map(|x| x.name.clone())),
+            PropertyValue::MultiSelect{ multi_select } => multi_select.iter().map(|x| x.name.clone()).collect::<Vec<String>>().into(),
+            PropertyValue::Date{ date } => date_to_value(date),
+            PropertyValue::Checkbox{ checkbox } => (*checkbox).into(),
+            PropertyValue::People{ people } => people.iter().map(user_name).collect::<Vec<String>>().into(),
+            PropertyValue::Relation{ relation } => relation.iter().map(|x| x.id.clone()).collect::<Vec<String>>().into(),
+            PropertyValue::Formula{ formula } => {
+                match formula{
+                    FormulaValue::String{ string } => optional_value(string.clone()),
+                    FormulaValue::Number{ number } => optional_value(*number),
+                    FormulaValue::Boolean{ boolean } => optional_value(*boolean),
+                    FormulaValue::Date{ date } => date_to_value(date),
+                    FormulaValue::Unknown => null_value()
+                }
+            },
+            PropertyValue::Rollup{ rollup } => {
+                match rollup{
+                    RollupValue::Number{ number } => optional_value(*number),
+                    RollupValue::Date{ date } => date_to_value(date),
+                    RollupValue::Array{ array } => array.iter().map(|x| x.to_payload_value()).collect::<Vec<Value>>().into(),
+                    RollupValue::Unknown => null_value()
+                }
+            },
+            PropertyValue::Url{ url: x } | PropertyValue::Email{ email: x } | PropertyValue::PhoneNumber{ phone_number: x } => optional_value(x.clone()),
+            PropertyValue::Files{ files } => files.iter().filter_map(|x| x.file.url().map(|x| x.to_string())).collect::<Vec<String>>().into(),
+            PropertyValue::CreatedTime{ created_time: x } | PropertyValue::LastEditedTime{ last_edited_time: x } => x.clone().into(),
+            PropertyValue::CreatedBy{ created_by: x } | PropertyValue::LastEditedBy{ last_edited_by: x } => user_name(x).into(),
+            PropertyValue::UniqueId{ .. } => self.to_plain_text().into(),
+            PropertyValue::Unknown => null_value()
+        }
+    }
+
+    /// Renders the property as plain text, joining multiple values with `, `.
+    pub fn to_plain_text(&self) -> String{
+        match self{
+            PropertyValue::Title{ title: x } | PropertyValue::RichText{ rich_text: x } => plain_text(x),
+            PropertyValue::Number{ number } => number.map(|x| x.to_string()).unwrap_or_default(),
+            PropertyValue::Select{ select: x } | PropertyValue::Status{ status: x } => x.as_ref().map(|x| x.name.clone()).unwrap_or_default(),
+            PropertyValue::MultiSelect{ multi_select } => multi_select.iter().map(|x| x.name.as_str()).collect::<Vec<&str>>().join(", "),
+            PropertyValue::Date{ date } => date_to_text(date),
+            PropertyValue::Checkbox{ checkbox } => checkbox.to_string(),
+            PropertyValue::People{ people } => people.iter().map(user_name).collect::<Vec<String>>().join(", "),
+            PropertyValue::Relation{ relation } => relation.iter().map(|x| x.id.as_str()).collect::<Vec<&str>>().join(", "),
+            PropertyValue::Formula{ formula } => {
+                match formula{
+                    FormulaValue::String{ string } => string.clone().unwrap_or_default(),
+                    FormulaValue::Number{ number } => number.map(|x| x.to_string()).unwrap_or_default(),
+                    FormulaValue::Boolean{ boolean } => boolean.map(|x| x.to_string()).unwrap_or_default(),
+                    FormulaValue::Date{ date } => date_to_text(date),
+                    FormulaValue::Unknown => String::new()
+                }
+            },
+            PropertyValue::Rollup{ rollup } => {
+                match rollup{
+                    RollupValue::Number{ number } => number.map(|x| x.to_string()).unwrap_or_default(),
+                    RollupValue::Date{ date } => date_to_text(date),
+                    RollupValue::Array{ array } => array.iter().map(|x| x.to_plain_text()).filter(|x| !x.is_empty()).collect::<Vec<String>>().join(", "),
+                    RollupValue::Unknown => String::new()
+                }
+            },
+            PropertyValue::Url{ url: x } | PropertyValue::Email{ email: x } | PropertyValue::PhoneNumber{ phone_number: x } => x.clone().unwrap_or_default(),
+            PropertyValue::Files{ files } => files.iter().map(|x| x.name.as_str()).collect::<Vec<&str>>().join(", "),
+            PropertyValue::CreatedTime{ created_time: x } | PropertyValue::LastEditedTime{ last_edited_time: x } => x.clone(),
+            PropertyValue::CreatedBy{ created_by: x } | PropertyValue::LastEditedBy{ last_edited_by: x } => user_name(x),
+            PropertyValue::UniqueId{ unique_id } => {
+                match (&unique_id.prefix, unique_id.number) {
+                    (Some(prefix), Some(number)) => format!("{}-{}", prefix, number),
+                    (None, Some(number)) => number.to_string(),
+                    _ => String::new()
+                }
+            },
+            PropertyValue::Unknown => String::new()
+        }
+    }
+}
+
+/// Converts every property of a page into a Qdrant payload keyed by property name.
+pub fn properties_to_payload(properties: &HashMap<String, PropertyValue>) -> Payload{
+    let mut payload = Payload::new();
+    for (property_name, property_value) in properties.iter(){
+        payload.insert(property_name, property_value.to_payload_value());
+    }
+    return payload;
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use serde_json::json;
+
+    /// Properties of a database row as returned by Notion, one of each type.
+    fn database_row_properties() -> HashMap<String, PropertyValue>{
+        return serde_json::from_value(json!({
+            "Name": { "id": "title", "type": "title", "title": [{ "type": "text", "text": { "content": "Pad Thai" }, "plain_text": "Pad Thai" }] },
+            "Notes": { "id": "a%3Db", "type": "rich_text", "rich_text": [{ "type": "text", "text": { "content": "Spicy" }, "plain_text": "Spicy" }] },
+            "Servings": { "id": "c", "type": "number", "number": 2 },
+            "Cuisine": { "id": "d", "type": "select", "select": { "id": "e", "name": "Thai", "color": "red" } },
+            "Course": { "id": "f", "type": "select", "select": null },
+            "Tags": { "id": "g", "type": "multi_select", "multi_select": [{ "id": "h", "name": "noodles", "color": "blue" }, { "id": "i", "name": "quick", "color": "green" }] },
+            "State": { "id": "j", "type": "status", "status": { "id": "k", "name": "Done", "color": "green" } },
+            "Cooked": { "id": "l", "type": "date", "date": { "start": "2023-11-02", "end": null, "time_zone": null } },
+            "Trip": { "id": "m", "type": "date", "date": { "start": "2023-11-02", "end": "2023-11-05" } },
+            "Planned": { "id": "n", "type": "date", "date": null },
+            "Vegan": { "id": "o", "type": "checkbox", "checkbox": true },
+            "Cooks": { "id": "p", "type": "people", "people": [{ "object": "user", "id": "user-1", "name": "Ada" }, { "object": "user", "id": "user-2" }] },
+            "Related": { "id": "q", "type": "relation", "relation": [{ "id": "page-2" }], "has_more": false },
+            "Cost": { "id": "r", "type": "formula", "formula": { "type": "number", "number": 7.5 } },
+            "Ingredients": { "id": "s", "type": "rollup", "rollup": { "type": "array", "function": "show_original", "array": [
+                { "type": "title", "title": [{ "type": "text", "text": { "content": "Rice noodles" }, "plain_text": "Rice noodles" }] }
+            ] } },
+            "Source": { "id": "t", "type": "url", "url": "https://example.com/pad-thai" },
+            "Contact": { "id": "u", "type": "email", "email": null },
+            "Phone": { "id": "v", "type": "phone_number", "phone_number": "+1 555 0100" },
+            "Photos": { "id": "w", "type": "files", "files": [{ "name": "plate.jpg", "type": "external", "external": { "url": "https://example.com/plate.jpg" } }] },
+            "Created": { "id": "x", "type": "created_time", "created_time": "2023-11-01T10:00:00.000Z" },
+            "Edited": { "id": "y", "type": "last_edited_time", "last_edited_time": "2023-11-03T10:00:00.000Z" },
+            "Author": { "id": "z", "type": "created_by", "created_by": { "object": "user", "id": "user-1", "name": "Ada" } },
+            "Editor": { "id": "aa", "type": "last_edited_by", "last_edited_by": { "object": "user", "id": "user-2" } },
+            "ID": { "id": "ab", "type": "unique_id", "unique_id": { "prefix": "REC", "number": 42 } },
+            "Button": { "id": "ac", "type": "button", "button": {} }
+        })).unwrap();
+    }
+
+    #[test]
+    fn every_property_type_is_deserialized(){
+        let properties = database_row_properties();
+        let plain_text_of = |property_name: &str| properties[property_name].to_plain_text();
+
+        assert_eq!(plain_text_of("Name"), "Pad Thai");
+        assert_eq!(plain_text_of("Notes"), "Spicy");
+        assert_eq!(plain_text_of("Servings"), "2");
+        assert_eq!(plain_text_of("Cuisine"), "Thai");
+        assert_eq!(plain_text_of("Course"), "");
+        assert_eq!(plain_text_of("Tags"), "noodles, quick");
+        assert_eq!(plain_text_of("State"), "Done");
+        assert_eq!(plain_text_of("Cooked"), "2023-11-02");
+        assert_eq!(plain_text_of("Trip"), "2023-11-02 → 2023-11-05");
+        assert_eq!(plain_text_of("Planned"), "");
+        assert_eq!(plain_text_of("Vegan"), "true");
+        assert_eq!(plain_text_of("Cooks"), "Ada, user-2");
+        assert_eq!(plain_text_of("Related"), "page-2");
+        assert_eq!(plain_text_of("Cost"), "7.5");
+        assert_eq!(plain_text_of("Ingredients"), "Rice noodles");
+        assert_eq!(plain_text_of("Source"), "https://example.com/pad-thai");
+        assert_eq!(plain_text_of("Contact"), "");
+        assert_eq!(plain_text_of("Phone"), "+1 555 0100");
+        assert_eq!(plain_text_of("Photos"), "plate.jpg");
+        assert_eq!(plain_text_of("Created"), "2023-11-01T10:00:00.000Z");
+        assert_eq!(plain_text_of("Edited"), "2023-11-03T10:00:00.000Z");
+        assert_eq!(plain_text_of("Author"), "Ada");
+        assert_eq!(plain_text_of("Editor"), "user-2");
+        assert_eq!(plain_text_of("ID"), "REC-42");
+        assert!(matches!(properties["Button"], PropertyValue::Unknown));
+    }
+
+    #[test]
+    fn unknown_formula_and_rollup_types_fall_back(){
+        let properties: HashMap<String, PropertyValue> = serde_json::from_value(json!({
+            "Formula": { "id": "a", "type": "formula", "formula": { "type": "array", "array": [] } },
+            "Rollup": { "id": "b", "type": "rollup", "rollup": { "type": "incomplete", "incomplete": {}, "function": "show_unique" } }
+        })).unwrap();
+
+        assert!(matches!(properties["Formula"], PropertyValue::Formula{ formula: FormulaValue::Unknown }));
+        assert!(matches!(properties["Rollup"], PropertyValue::Rollup{ rollup: RollupValue::Unknown }));
+        assert!(properties["Formula"].to_payload_value().is_null());
+        assert_eq!(properties["Rollup"].to_plain_text(), "");
+    }
+
+    #[test]
+    fn properties_become_typed_payload_values(){
+        let payload: HashMap<String, Value> = properties_to_payload(&database_row_properties()).into();
+        let payload_json: HashMap<String, serde_json::Value> = payload.into_iter().map(|(x, y)| (x, y.into_json())).collect();
+
+        assert_eq!(payload_json["Name"], json!("Pad Thai"));
+        assert_eq!(payload_json["Servings"], json!(2.0));
+        assert_eq!(payload_json["Cuisine"], json!("Thai"));
+        assert_eq!(payload_json["Course"], json!(null));
+        assert_eq!(payload_json["Tags"], json!(["noodles", "quick"]));
+        assert_eq!(payload_json["Cooked"], json!("2023-11-02"));
+        assert_eq!(payload_json["Trip"], json!({ "start": "2023-11-02", "end": "2023-11-05" }));
+        assert_eq!(payload_json["Planned"], json!(null));
+        assert_eq!(payload_json["Vegan"], json!(true));
+        assert_eq!(payload_json["Cooks"], json!(["Ada", "user-2"]));
+        assert_eq!(payload_json["Related"], json!(["page-2"]));
+        assert_eq!(payload_json["Cost"], json!(7.5));
+        assert_eq!(payload_json["Ingredients"], json!(["Rice noodles"]));
+        assert_eq!(payload_json["Contact"], json!(null));
+        assert_eq!(payload_json["Photos"], json!(["https://example.com/plate.jpg"]));
+        assert_eq!(payload_json["Editor"], json!("user-2"));
+        assert_eq!(payload_json["ID"], json!("REC-42"));
+        assert_eq!(payload_json["Button"], json!(null));
+        assert_eq!(payload_json.len(), 25);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::notion_error::NotionError;
+use rand::Rng;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Notion's documented average limit is three requests per second per integration.
+pub const DEFAULT_REQUESTS_PER_SECOND: f64 = 3.0;
+
+/// Number of requests that may be sent back to back before the limiter kicks in.
+pub const DEFAULT_BURST_SIZE: u32 = 3;
+
+/// Lowest refill rate the limiter accepts, one request every ~17 minutes. Slower rates
+/// would overflow the `Duration` the limiter waits for.
+pub const MIN_REQUESTS_PER_SECOND: f64 = 0.001;
+
+/// Client-side token bucket that spaces out requests to stay under Notion's rate limit.
+#[derive(Debug)]
+pub struct RateLimiter{
+    capacity: f64,
+    refill_per_second: f64,
+    bucket: Mutex<TokenBucket>
+}
+
+#[derive(Debug)]
+struct TokenBucket{
+    tokens: f64,
+    last_refill: Instant
+}
+
+impl Default for RateLimiter{
+    fn default() -> Self{
+        return Self::new(DEFAULT_REQUESTS_PER_SECOND, DEFAULT_BURST_SIZE);
+    }
+}
+
+impl RateLimiter{
+    /// Rates below `MIN_REQUESTS_PER_SECOND` are raised to it, and rates that are not
+    /// finite fall back to `DEFAULT_REQUESTS_PER_SECOND`.
+    pub fn new(requests_per_second: f64, burst_size: u32) -> Self{
+        let capacity = f64::from(burst_size.max(1));
+        let refill_per_second = match requests_per_second.is_finite() {
+            true => requests_per_second.max(MIN_REQUESTS_PER_SECOND),
+            false => DEFAULT_REQUESTS_PER_SECOND
+        };
+
+        return RateLimiter{
+            capacity,
+            refill_per_second,
+            bucket: Mutex::new(TokenBucket{
+                tokens: capacity,
+                last_refill: Instant::now()
+            })
+        };
+    }
+
+    /// Waits until a token is available and consumes it.
+    pub async fn acquire(&self){
+        loop {
+            let wait_time = {
+                let mut bucket = self.bucket.lock().await;
+
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity);
+                bucket.last_refill = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill_per_second)
+            };
+
+            tokio::time::sleep(wait_time).await;
+        }
+    }
+}
+
+/// How failed Notion requests are retried.
+///
+/// Rate limited requests wait for the `Retry-After` duration sent by Notion, at most
+/// `max_delay`; other
+/// retryable failures (5xx responses, timeouts, connection errors) back off
+/// exponentially from `base_delay` up to `max_delay`, with random jitter.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy{
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration
+}
+
+impl Default for RetryPolicy{
+    fn default() -> Self{
+        return RetryPolicy{
+            max_retries: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30)
+        };
+    }
+}
+
+impl RetryPolicy{
+    /// A policy that never retries.
+    pub fn disabled() -> Self{
+        return RetryPolicy{
+            max_retries: 0,
+            ..Default::default()
+        };
+    }
+
+    /// Returns how long to wait before retry number `attempt` (starting at 0) after
+    /// `error`, or `None` if the request should not be retried.
+    pub fn retry_delay(&self, attempt: u32, error: &NotionError) -> Option<Duration>{
+        if attempt >= self.max_retries || !error.is_retryable() {
+            return None;
+        }
+
+        if let NotionError::RateLimited{ retry_after: Some(retry_after) } = error {
+            return Some((*retry_after).min(self.max_delay));
+        }
+
+        return Some(self.backoff_delay(attempt));
+    }
+
+    /// Exponential backoff with "equal jitter": half of the delay is fixed, the other half
+    /// random, so concurrent clients do not retry in lockstep.
+    fn backoff_delay(&self, attempt: u32) -> Duration{
+        let exponential_delay = self.base_delay.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_delay);
+        let half_delay = exponential_delay / 2;
+        let jitter = rand::thread_rng().gen_range(0.0..=1.0);
+
+        return half_delay + half_delay.mul_f64(jitter);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn retry_after_is_capped_at_max_delay(){
+        let retry_policy = RetryPolicy::default();
+
+        let short_wait = NotionError::RateLimited{ retry_after: Some(Duration::from_secs(2)) };
+        assert_eq!(retry_policy.retry_delay(0, &short_wait), Some(Duration::from_secs(2)));
+
+        let huge_wait = NotionError::RateLimited{ retry_after: Some(Duration::MAX) };
+        assert_eq!(retry_policy.retry_delay(0, &huge_wait), Some(retry_policy.max_delay));
+    }
+
+    #[test]
+    fn backoff_doubles_within_its_jitter_bounds(){
+        let retry_policy = RetryPolicy{
+            max_retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1)
+        };
+        let server_error = NotionError::Api{ status: reqwest::StatusCode::BAD_GATEWAY, code: String::new(), message: String::new() };
+
+        // Equal jitter keeps every delay between half of the exponential delay and all of it.
+        for (attempt, exponential_delay) in [(0, 100), (1, 200), (2, 400), (3, 800), (4, 1000), (9, 1000)]{
+            let exponential_delay = Duration::from_millis(exponential_delay);
+            for _ in 0..20{
+                let retry_delay = retry_policy.retry_delay(attempt, &server_error).unwrap();
+                assert!(retry_delay >= exponential_delay / 2 && retry_delay <= exponential_delay, "attempt {}: {:?}", attempt, retry_delay);
+            }
+        }
+    }
+
+    #[test]
+    fn retries_stop_after_max_retries_and_on_permanent_errors(){
+        let retry_policy = RetryPolicy::default();
+        let rate_limited = NotionError::RateLimited{ retry_after: None };
+
+        assert!(retry_policy.retry_delay(retry_policy.max_retries - 1, &rate_limited).is_some());
+        assert_eq!(retry_policy.retry_delay(retry_policy.max_retries, &rate_limited), None);
+        assert_eq!(RetryPolicy::disabled().retry_delay(0, &rate_limited), None);
+
+        let not_found = NotionError::NotFound{ code: String::new(), message: String::new() };
+        assert_eq!(retry_policy.retry_delay(0, &not_found), None);
+    }
+
+    #[test]
+    fn rate_and_burst_are_clamped(){
+        let rate_limiter = RateLimiter::new(0.0, 0);
+        assert_eq!(rate_limiter.refill_per_second, MIN_REQUESTS_PER_SECOND);
+        assert_eq!(rate_limiter.capacity, 1.0);
+
+        assert_eq!(RateLimiter::new(-3.0, 5).refill_per_second, MIN_REQUESTS_PER_SECOND);
+        assert_eq!(RateLimiter::new(f64::NAN, 5).refill_per_second, DEFAULT_REQUESTS_PER_SECOND);
+        assert_eq!(RateLimiter::new(f64::INFINITY, 5).refill_per_second, DEFAULT_REQUESTS_PER_SECOND);
+    }
+
+    #[tokio::test]
+    async fn a_zero_rate_limiter_still_grants_its_burst(){
+        let rate_limiter = RateLimiter::new(0.0, 0);
+        tokio::time::timeout(Duration::from_secs(1), rate_limiter.acquire()).await.unwrap();
+    }
+}
@@ -0,0 +1,568 @@
+//! Incremental synchronisation of Notion pages into a Qdrant collection.
+//!
+//! Indexed pages are recorded in a `SyncStateStore` together with their
+//! `last_edited_time`, embedding model and a hash of the indexed content, so later runs
+//! only re-embed pages that actually changed and remove the points of pages that
+//! disappeared: pages that were archived, trashed, deleted or are no longer shared with
+//! the integration, and pages that moved out of the crawled root.
+//!
+//! A sync runs as a pipeline of three stages connected by bounded queues. Pages are
+//! fetched and chunked with bounded concurrency, embedded on a pool of blocking worker
+//! threads, and upserted to Qdrant by a single writer that also updates the sync state.
+//! A full queue pauses the stage feeding it, so a slow embedder or Qdrant never lets
+//! fetched pages pile up in memory.
+
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use qdrant_client::prelude::Payload;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use uuid::Uuid;
+use crate::block_chunker::chunk_block_tree;
+use crate::embedder::EmbeddingError;
+use crate::notion_error::NotionError;
+use crate::notion_markdown::blocks_to_markdown;
+use crate::notion_pages_setup::NotionPagesAPI;
+use crate::notion_properties::{properties_to_payload, PropertyValue};
+use crate::point_ids::page_point_ids;
+use crate::qdrantdb::{QdrantDBStruct, QdrantError};
+use crate::sync_state_store::{parse_notion_timestamp, unix_timestamp, IndexedPageRecord, SyncStateError, SyncStateStore, DEFAULT_SYNC_STATE_PATH};
+use crate::text_chunker::{chunk_text, ChunkingStrategy, TextChunk};
+
+/// Payload key holding the Notion page ID of every point, used to delete a page's points
+/// even when the sync state does not know about all of them.
+const PAGE_ID_PAYLOAD_KEY: &str = "page_id";
+
+/// Payload key the properties of database rows are nested under, so a property called
+/// e.g. `title` cannot overwrite the page metadata. Filter on them as `properties.<name>`.
+const PROPERTIES_PAYLOAD_KEY: &str = "properties";
+
+/// Bumped whenever the payload written for a page changes, so pages indexed with an older
+/// layout are rewritten by the next sync.
+const PAYLOAD_LAYOUT_VERSION: u32 = 2;
+
+/// Notion rounds `last_edited_time` down to the minute, so an edit made in the same minute
+/// as a fetch keeps the timestamp the fetch saw. Only content fetched at least this long
+/// after its `last_edited_time` (a minute, plus a minute of clock skew) is known to be
+/// current while the timestamp stays the same.
+const LAST_EDITED_TIME_PRECISION_SECS: u64 = 120;
+
+/// Pages fetched from Notion at the same time.
+pub const DEFAULT_FETCH_CONCURRENCY: usize = 4;
+
+/// Pages embedded at the same time, each on its own blocking thread.
+pub const DEFAULT_EMBEDDING_WORKERS: usize = 2;
+
+/// Pages that may wait between two stages before the earlier stage is paused.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 8;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError{
+    #[error(transparent)]
+    Notion(#[from] NotionError),
+
+    #[error(transparent)]
+    State(#[from] SyncStateError)
+}
+
+/// A page picked for indexing, either by crawling a root page or through search.
+#[derive(Debug, Clone)]
+pub struct PageToIndex{
+    pub id: String,
+    pub title: String,
+    /// Human readable location of the page, e.g. `Recipes / Italian / Carbonara`.
+    pub path: String,
+    /// Property values of database rows; empty for regular pages.
+    pub properties: HashMap<String, PropertyValue>
+}
+
+/// Outcome of a sync run, as lists of page IDs.
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport{
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub unchanged: Vec<String>,
+    pub deleted: Vec<String>,
+    pub failed: Vec<String>
+}
+
+/// A changed page, chunked and ready to be embedded and written.
+struct PreparedPage{
+    /// The record stored once the page's points are written.
+    record: IndexedPageRecord,
+    texts: Vec<String>,
+    payloads: Vec<Payload>,
+    /// Filled in by the embedding stage, one vector per text.
+    embeddings: Vec<Vec<f32>>
+}
+
+/// What the fetch stage decided for a page, passed down the pipeline to the writer.
+enum PageOutcome{
+    /// Nothing to embed. A refreshed record is stored when only the timestamp moved.
+    Unchanged{ page_id: String, refreshed_record: Option<IndexedPageRecord> },
+    /// The page was archived, trashed or can no longer be retrieved.
+    Deleted{ page_id: String },
+    Changed(PreparedPage),
+    Failed{ page_id: String }
+}
+
+/// Syncs a set of Notion pages into a Qdrant collection, embedding only what changed.
+pub struct SyncEngine<'a>{
+    notion_api: &'a NotionPagesAPI,
+    qdrant_db: &'a QdrantDBStruct,
+    collection_name: String,
+    state_path: PathBuf,
+    chunking_strategy: ChunkingStrategy,
+    fetch_concurrency: usize,
+    embedding_workers: usize,
+    queue_capacity: usize
+}
+
+impl<'a> SyncEngine<'a>{
+    pub fn new(notion_api: &'a NotionPagesAPI, qdrant_db: &'a QdrantDBStruct, collection_name: &str) -> Self{
+        return SyncEngine{
+            notion_api,
+            qdrant_db,
+            collection_name: collection_name.to_string(),
+            state_path: PathBuf::from(DEFAULT_SYNC_STATE_PATH),
+            chunking_strategy: ChunkingStrategy::default(),
+            fetch_concurrency: DEFAULT_FETCH_CONCURRENCY,
+            embedding_workers: DEFAULT_EMBEDDING_WORKERS,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY
+        };
+    }
+
+    pub fn state_path(mut self, state_path: impl Into<PathBuf>) -> Self{
+        self.state_path = state_path.into();
+        return self;
+    }
+
+    /// How page content is split before embedding; each chunk becomes its own point.
+    pub fn chunking_strategy(mut self, chunking_strategy: ChunkingStrategy) -> Self{
+        self.chunking_strategy = chunking_strategy;
+        return self;
+    }
+
+    /// How many pages are fetched from Notion at the same time. Requests still go
+    /// through the client's rate limiter.
+    pub fn fetch_concurrency(mut self, fetch_concurrency: usize) -> Self{
+        self.fetch_concurrency = fetch_concurrency.max(1);
+        return self;
+    }
+
+    /// How many pages are embedded at the same time on blocking threads.
+    pub fn embedding_workers(mut self, embedding_workers: usize) -> Self{
+        self.embedding_workers = embedding_workers.max(1);
+        return self;
+    }
+
+    /// How many pages may wait between two stages of the pipeline.
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Self{
+        self.queue_capacity = queue_capacity.max(1);
+        return self;
+    }
+
+    /// Brings the collection in line with `pages_to_index`, which must be the complete
+    /// set of pages that should be searchable: pages indexed previously but missing from
+    /// it are treated as removed and their points are deleted.
+    pub async fn sync(&self, pages_to_index: &[PageToIndex]) -> Result<SyncReport, SyncError>{
+        let mut sync_state = SyncStateStore::open(&self.state_path)?;
+        let mut sync_report = SyncReport::default();
+
+        // The fetch stage decides against a snapshot; only the writer touches the store.
+        let previous_records: HashMap<String, IndexedPageRecord> = sync_state.pages(&self.collection_name).into_iter()
+            .map(|x| (x.page_id.clone(), x.clone()))
+            .collect();
+        let seen_page_ids: HashSet<&String> = pages_to_index.iter().map(|x| &x.id).collect();
+
+        let (fetched_tx, fetched_rx) = mpsc::channel::<PageOutcome>(self.queue_capacity);
+        let (embedded_tx, mut embedded_rx) = mpsc::channel::<PageOutcome>(self.queue_capacity);
+
+        let fetch_stage = async {
+            // Owning the sender closes the queue once every page is fetched.
+            let mut fetched_tx = fetched_tx;
+            // Indexing rather than borrowing items keeps the future `Send`; closures taking
+            // references trip up the compiler's `Send` inference.
+            let mut page_outcomes = futures::stream::iter(0..pages_to_index.len())
+                .map(|x| self.prepare_page(&pages_to_index[x], previous_records.get(&pages_to_index[x].id)))
+                .buffer_unordered(self.fetch_concurrency);
+
+            while let Some(page_outcome) = page_outcomes.next().await {
+                // The writer stopped, e.g. because the sync state could not be saved.
+                if fetched_tx.send(page_outcome).await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        let embed_stage = fetched_rx
+            .map(|x| self.embed_page(x))
+            .buffered(self.embedding_workers)
+            .map(Ok)
+            .forward(embedded_tx);
+
+        let write_stage = async {
+            while let Some(page_outcome) = embedded_rx.next().await {
+                self.write_page(page_outcome, &mut sync_state, &mut sync_report).await;
+
+                // Persist after every page so an interrupted run does not redo finished work.
+                sync_state.save()?;
+            }
+            return Ok::<(), SyncStateError>(());
+        };
+
+        let (_, _, write_result) = futures::join!(fetch_stage, embed_stage, write_stage);
+        write_result?;
+
+        let removed_page_ids: Vec<String> = sync_state.pages(&self.collection_name).into_iter()
+            .map(|x| x.page_id.clone())
+            .filter(|x| !seen_page_ids.contains(x))
+            .collect();
+        for removed_page_id in removed_page_ids{
+            log::info!("Page {} was removed or moved out of the root, deleting its points", removed_page_id);
+            match self.delete_page(&removed_page_id, &mut sync_state).await {
+                Ok(_) => sync_report.deleted.push(removed_page_id),
+                Err(err) => {
+                    log::error!("Failed to delete the points of page {}: {}", removed_page_id, err);
+                    sync_report.failed.push(removed_page_id);
+                }
+            }
+        }
+        sync_state.save()?;
+
+        log::info!(
+            "Sync finished: {} added, {} updated, {} unchanged, {} deleted, {} failed",
+            sync_report.added.len(), sync_report.updated.len(), sync_report.unchanged.len(), sync_report.deleted.len(), sync_report.failed.len()
+        );
+        return Ok(sync_report);
+    }
+
+    /// Fetch stage: decides what to do with a page and, if it changed, chunks it.
+    async fn prepare_page(&self, page_to_index: &PageToIndex, previous_record: Option<&IndexedPageRecord>) -> PageOutcome{
+        match self.try_prepare_page(page_to_index, previous_record).await {
+            Ok(page_outcome) => {
+                return page_outcome;
+            }
+            Err(err) => {
+                log::error!("Failed to sync page {} ({}): {}", page_to_index.title, page_to_index.id, err);
+                return PageOutcome::Failed{ page_id: page_to_index.id.clone() };
+            }
+        }
+    }
+
+    async fn try_prepare_page(&self, page_to_index: &PageToIndex, previous_record: Option<&IndexedPageRecord>) -> Result<PageOutcome, NotionError>{
+        let page = match self.notion_api.retrieve_page(&page_to_index.id).await {
+            Ok(page) => page,
+            Err(NotionError::NotFound{ .. }) => {
+                // Deleted for good, or no longer shared with the integration.
+                log::info!("Page {} can no longer be retrieved, deleting its points", page_to_index.title);
+                return Ok(PageOutcome::Deleted{ page_id: page_to_index.id.clone() });
+            }
+            Err(err) => return Err(err)
+        };
+
+        if page.archived || page.in_trash {
+            log::info!("Page {} was archived or trashed, deleting its points", page_to_index.title);
+            return Ok(PageOutcome::Deleted{ page_id: page_to_index.id.clone() });
+        }
+
+        let embedding_model = self.qdrant_db.embeddings_model.model_id().to_string();
+        let settings_hash = self.settings_hash(page_to_index);
+
+        // Pages indexed with another embedding model always have to be re-embedded.
+        let previous_record = previous_record.filter(|x| x.embedding_model == embedding_model);
+        if let Some(previous_record) = previous_record {
+            // A new path, new properties or another chunking strategy change the points even
+            // when the page itself was not edited.
+            let is_unedited = previous_record.last_edited_time.is_some() && previous_record.last_edited_time == page.last_edited_time;
+            if is_unedited && previous_record.settings_hash == settings_hash {
+                if fetched_after_last_edit(previous_record) {
+                    log::debug!("Page {} unchanged since {:?}", page_to_index.title, page.last_edited_time);
+                    return Ok(PageOutcome::Unchanged{ page_id: page_to_index.id.clone(), refreshed_record: None });
+                }
+                log::debug!("Page {} was last fetched within a minute of its last edit, comparing its content", page_to_index.title);
+            }
+        }
+
+        let fetched_at = unix_timestamp();
+        let (content, chunks) = self.fetch_page_chunks(&page_to_index.id).await?;
+        let content_hash = self.content_hash(page_to_index, &content);
+
+        // Edits that do not change the indexed content (e.g. comments) only bump the
+        // recorded timestamp.
+        if let Some(previous_record) = previous_record {
+            if previous_record.content_hash == content_hash {
+                let refreshed_record = IndexedPageRecord{
+                    last_edited_time: page.last_edited_time.clone(),
+                    settings_hash,
+                    fetched_at,
+                    ..previous_record.clone()
+                };
+                return Ok(PageOutcome::Unchanged{ page_id: page_to_index.id.clone(), refreshed_record: Some(refreshed_record) });
+            }
+        }
+
+        let mut qdrant_payload = Payload::new();
+        qdrant_payload.insert(PROPERTIES_PAYLOAD_KEY, properties_to_payload(&page_to_index.properties));
+        qdrant_payload.insert("page_path", page_to_index.path.to_string());
+        for (metadata_key, metadata_value) in page.metadata().to_payload_map(){
+            qdrant_payload.insert(metadata_key, metadata_value);
+        }
+
+        log::debug!("Page {} split into {} chunks", page_to_index.title, chunks.len());
+
+        let chunk_payloads: Vec<Payload> = chunks.iter().map(|x| {
+            let mut chunk_payload = qdrant_payload.clone();
+            x.insert_into_payload(&mut chunk_payload);
+            return chunk_payload;
+        }).collect();
+
+        return Ok(PageOutcome::Changed(PreparedPage{
+            record: IndexedPageRecord{
+                page_id: page_to_index.id.clone(),
+                title: page_to_index.title.clone(),
+                embedding_model,
+                last_edited_time: page.last_edited_time.clone(),
+                content_hash,
+                settings_hash,
+                point_ids: page_point_ids(&page_to_index.id, chunks.len()),
+                indexed_at: unix_timestamp(),
+                fetched_at
+            },
+            texts: chunks.iter().map(|x| x.text_with_breadcrumb()).collect(),
+            payloads: chunk_payloads,
+            embeddings: Vec::new()
+        }));
+    }
+
+    /// Embedding stage: embeds the chunks of a changed page on a blocking thread, since
+    /// embedding is CPU bound and would otherwise stall the async runtime.
+    async fn embed_page(&self, page_outcome: PageOutcome) -> PageOutcome{
+        let mut prepared_page = match page_outcome {
+            PageOutcome::Changed(prepared_page) if !prepared_page.texts.is_empty() => prepared_page,
+            page_outcome => return page_outcome
+        };
+
+        let embeddings_model = Arc::clone(&self.qdrant_db.embeddings_model);
+        let embedding_batch_size = self.qdrant_db.get_embedding_batch_size();
+        let texts = prepared_page.texts.clone();
+
+        // A panicking embedding task fails the page just like an embedding error.
+        let embedding_result = tokio::task::spawn_blocking(move || embeddings_model.embed_documents(texts, Some(embedding_batch_size))).await
+            .map_err(|err| EmbeddingError::Embedding(err.into()))
+            .and_then(|x| x);
+
+        match embedding_result {
+            Ok(embeddings) => {
+                prepared_page.embeddings = embeddings;
+                return PageOutcome::Changed(prepared_page);
+            }
+            Err(err) => {
+                log::error!("Failed to embed page {} ({}): {}", prepared_page.record.title, prepared_page.record.page_id, err);
+                return PageOutcome::Failed{ page_id: prepared_page.record.page_id };
+            }
+        }
+    }
+
+    /// Write stage: applies the outcome of a page to Qdrant and the sync state.
+    async fn write_page(&self, page_outcome: PageOutcome, sync_state: &mut SyncStateStore, sync_report: &mut SyncReport){
+        match page_outcome {
+            PageOutcome::Unchanged{ page_id, refreshed_record } => {
+                if let Some(refreshed_record) = refreshed_record {
+                    sync_state.upsert(&self.collection_name, refreshed_record);
+                }
+                sync_report.unchanged.push(page_id);
+            }
+            PageOutcome::Deleted{ page_id } => {
+                match self.delete_page(&page_id, sync_state).await {
+                    Ok(true) => sync_report.deleted.push(page_id),
+                    Ok(false) => {}
+                    Err(err) => {
+                        log::error!("Failed to delete the points of page {}: {}", page_id, err);
+                        sync_report.failed.push(page_id);
+                    }
+                }
+            }
+            PageOutcome::Failed{ page_id } => {
+                sync_report.failed.push(page_id);
+            }
+            PageOutcome::Changed(prepared_page) => {
+                let page_id = prepared_page.record.page_id.clone();
+                match self.write_changed_page(prepared_page, sync_state).await {
+                    Ok(true) => sync_report.updated.push(page_id),
+                    Ok(false) => sync_report.added.push(page_id),
+                    Err(err) => {
+                        log::error!("Failed to write page {}: {}", page_id, err);
+                        sync_report.failed.push(page_id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes the chunks of a changed page and records it, returning whether the page was
+    /// indexed before. Nothing is recorded when the write fails, so the next sync retries.
+    async fn write_changed_page(&self, prepared_page: PreparedPage, sync_state: &mut SyncStateStore) -> Result<bool, QdrantError>{
+        let PreparedPage{ mut record, texts, payloads, embeddings } = prepared_page;
+
+        let previous_point_ids = match sync_state.get(&self.collection_name, &record.page_id) {
+            Some(previous_record) => Some(previous_record.point_ids.clone()),
+            None => {
+                // Nothing recorded for the page, e.g. after a state reset: clear whatever an
+                // earlier run may have left behind before writing the new chunks.
+                self.qdrant_db.delete_points_by_filter(&self.collection_name, HashMap::from([(PAGE_ID_PAYLOAD_KEY, record.page_id.as_str())])).await?;
+                None
+            }
+        };
+
+        self.qdrant_db.add_embedded_stuff_to_collection(&self.collection_name, texts, embeddings, record.point_ids.clone(), payloads).await?;
+
+        if let Some(previous_point_ids) = &previous_point_ids {
+            // Points that were not overwritten by this upsert would otherwise linger.
+            let current_point_ids: HashSet<&Uuid> = record.point_ids.iter().collect();
+            let stale_point_ids: Vec<Uuid> = previous_point_ids.iter().filter(|x| !current_point_ids.contains(x)).copied().collect();
+            if let Err(err) = self.qdrant_db.delete_points(&self.collection_name, stale_point_ids.clone()).await {
+                // The new chunks are in place; keep tracking the stale points so deleting the
+                // page later still removes them.
+                log::error!("Failed to delete stale points of page {}: {}", record.page_id, err);
+                record.point_ids.extend(stale_point_ids);
+            }
+        }
+
+        sync_state.upsert(&self.collection_name, record);
+        return Ok(previous_point_ids.is_some());
+    }
+
+    /// Fetches the content of a page and splits it with the configured strategy.
+    async fn fetch_page_chunks(&self, page_id: &str) -> Result<(String, Vec<TextChunk>), NotionError>{
+        let block_tree = self.notion_api.get_page_block_tree(page_id).await?;
+
+        if let ChunkingStrategy::BlockTree{ max_chars } = self.chunking_strategy {
+            let chunked_page = chunk_block_tree(&block_tree, max_chars);
+            return Ok((chunked_page.page_text, chunked_page.chunks));
+        }
+
+        let content = blocks_to_markdown(&block_tree);
+        let chunks = chunk_text(&content, &self.chunking_strategy);
+        return Ok((content, chunks));
+    }
+
+    /// Deletes every point of `page_id` and forgets it, returning whether the page was
+    /// indexed before. Points are also deleted by payload so that ones missing from the
+    /// sync state (e.g. after a reset) do not linger. The page stays recorded when a
+    /// delete fails, so the next sync retries it.
+    async fn delete_page(&self, page_id: &str, sync_state: &mut SyncStateStore) -> Result<bool, QdrantError>{
+        self.qdrant_db.delete_points_by_filter(&self.collection_name, HashMap::from([(PAGE_ID_PAYLOAD_KEY, page_id)])).await?;
+
+        let previous_point_ids = match sync_state.get(&self.collection_name, page_id) {
+            Some(previous_record) => previous_record.point_ids.clone(),
+            None => return Ok(false)
+        };
+        self.qdrant_db.delete_points(&self.collection_name, previous_point_ids).await?;
+
+        sync_state.remove(&self.collection_name, page_id);
+        return Ok(true);
+    }
+
+    /// Hashes everything that ends up in the collection for a page: its content, title,
+    /// path and properties, how the content is chunked and how the payload is laid out.
+    fn content_hash(&self, page_to_index: &PageToIndex, content: &str) -> String{
+        let sorted_properties: BTreeMap<&String, String> = page_to_index.properties.iter().map(|(x, y)| (x, y.to_plain_text())).collect();
+
+        let mut hasher = Sha256::new();
+        hasher.update(PAYLOAD_LAYOUT_VERSION.to_le_bytes());
+        hasher.update(content.as_bytes());
+        hasher.update([0u8]);
+        hasher.update(page_to_index.title.as_bytes());
+        hasher.update([0u8]);
+        hasher.update(page_to_index.path.as_bytes());
+        hasher.update([0u8]);
+        hasher.update(format!("{:?}", self.chunking_strategy).as_bytes());
+        for (property_name, property_text) in sorted_properties{
+            hasher.update([0u8]);
+            hasher.update(property_name.as_bytes());
+            hasher.update([0u8]);
+            hasher.update(property_text.as_bytes());
+        }
+
+        return format!("{:x}", hasher.finalize());
+    }
+
+    /// Hashes what `content_hash` covers besides the content, which is known without
+    /// fetching the page's blocks.
+    fn settings_hash(&self, page_to_index: &PageToIndex) -> String{
+        return self.content_hash(page_to_index, "");
+    }
+}
+
+/// Whether `record` was fetched late enough after its `last_edited_time` that an unchanged
+/// timestamp proves the page was not edited since.
+fn fetched_after_last_edit(record: &IndexedPageRecord) -> bool{
+    let last_edited_at = match record.last_edited_time.as_deref().and_then(parse_notion_timestamp) {
+        Some(last_edited_at) => last_edited_at,
+        None => return false
+    };
+    // Content is fetched just before it is written, so older records fall back to that.
+    let fetched_at = match record.fetched_at {
+        0 => record.indexed_at,
+        fetched_at => fetched_at
+    };
+    return fetched_at >= last_edited_at + LAST_EDITED_TIME_PRECISION_SECS;
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::notion_crawler::NotionCrawler;
+    use crate::notion_rate_limit::RetryPolicy;
+
+    fn assert_send<T: Send>(_: &T){}
+
+    /// The client is meant to be cloned into spawned tasks, so none of these futures may
+    /// hold anything that is not `Send`. This only has to compile.
+    #[allow(dead_code)]
+    fn notion_futures_are_send(notion_api: &NotionPagesAPI, qdrant_db: &QdrantDBStruct){
+        assert_send(&notion_api.get_page_content("page"));
+        assert_send(&notion_api.get_page_block_tree("page"));
+        assert_send(&NotionCrawler::new(notion_api).crawl("page"));
+        assert_send(&SyncEngine::new(notion_api, qdrant_db, "collection").sync(&[]));
+    }
+
+    #[tokio::test]
+    async fn cloned_client_can_be_used_from_spawned_tasks(){
+        let notion_api = NotionPagesAPI::builder()
+            .token("secret")
+            .base_url("http://127.0.0.1:9")
+            .retry_policy(RetryPolicy::disabled())
+            .build()
+            .unwrap();
+        let task_notion_api = notion_api.clone();
+        let page_content = tokio::spawn(async move { task_notion_api.get_page_content("page").await }).await.unwrap();
+        assert!(matches!(page_content, Err(NotionError::Transport(_))));
+    }
+
+    fn record_edited_at(last_edited_time: &str, fetched_at: u64) -> IndexedPageRecord{
+        return IndexedPageRecord{
+            last_edited_time: Some(last_edited_time.to_string()),
+            indexed_at: fetched_at + 5,
+            fetched_at,
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn timestamps_are_only_trusted_once_their_minute_has_passed(){
+        // 2022-07-06T20:25:00.000Z
+        let last_edited_at = 1657139100;
+
+        assert!(!fetched_after_last_edit(&record_edited_at("2022-07-06T20:25:00.000Z", last_edited_at + 30)));
+        assert!(!fetched_after_last_edit(&record_edited_at("2022-07-06T20:25:00.000Z", last_edited_at + 119)));
+        assert!(fetched_after_last_edit(&record_edited_at("2022-07-06T20:25:00.000Z", last_edited_at + 120)));
+        assert!(!fetched_after_last_edit(&record_edited_at("not a timestamp", last_edited_at + 3600)));
+
+        let legacy_record = IndexedPageRecord{ fetched_at: 0, ..record_edited_at("2022-07-06T20:25:00.000Z", last_edited_at + 3600) };
+        assert!(fetched_after_last_edit(&legacy_record), "records without fetched_at fall back to indexed_at");
+    }
+}
@@ -0,0 +1,677 @@
+//! Typed representation of the objects returned by the Notion API.
+//!
+//! Fields are defaulted wherever Notion may omit them, and every tagged enum carries an
+//! `Unknown` variant so new block, parent or rich text types do not break parsing.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::notion_properties::PropertyValue;
+
+/// A paginated list response, e.g. from `GET /v1/blocks/{id}/children`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PaginatedList<T>{
+    #[serde(default = "Vec::new")]
+    pub results: Vec<T>,
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+    #[serde(default)]
+    pub has_more: bool
+}
+
+impl<T> PaginatedList<T>{
+    /// Splits the list into its results and the cursor of the next page, which is `None`
+    /// once Notion reports `has_more == false`.
+    pub fn into_page(self) -> (Vec<T>, Option<String>){
+        let next_cursor = match self.has_more {
+            true => self.next_cursor,
+            false => None
+        };
+        return (self.results, next_cursor);
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Annotations{
+    #[serde(default)]
+    pub bold: bool,
+    #[serde(default)]
+    pub italic: bool,
+    #[serde(default)]
+    pub strikethrough: bool,
+    #[serde(default)]
+    pub underline: bool,
+    #[serde(default)]
+    pub code: bool,
+    #[serde(default)]
+    pub color: Option<String>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RichText{
+    #[serde(default)]
+    pub plain_text: String,
+    #[serde(default)]
+    pub href: Option<String>,
+    #[serde(default)]
+    pub annotations: Annotations,
+    #[serde(flatten)]
+    pub kind: RichTextKind
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RichTextKind{
+    Text{ text: TextContent },
+    Mention{ mention: serde_json::Value },
+    Equation{ equation: EquationContent },
+    #[serde(other)]
+    Unknown
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TextContent{
+    #[serde(default)]
+    pub content: String,
+    #[serde(default)]
+    pub link: Option<Link>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Link{
+    pub url: String
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct EquationContent{
+    #[serde(default)]
+    pub expression: String
+}
+
+/// Notion accepts IDs with and without dashes, in any case; this is the form used to
+/// compare them.
+pub fn normalize_notion_id(notion_id: &str) -> String{
+    return notion_id.replace('-', "").to_lowercase();
+}
+
+/// Concatenates the `plain_text` of every rich text segment.
+pub fn plain_text(rich_text: &[RichText]) -> String{
+    return rich_text.iter().map(|x| x.plain_text.as_str()).collect();
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct User{
+    pub id: String,
+    #[serde(default, rename = "type")]
+    pub user_type: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+    #[serde(default)]
+    pub person: Option<Person>
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Person{
+    #[serde(default)]
+    pub email: Option<String>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Parent{
+    PageId{ page_id: String },
+    DatabaseId{ database_id: String },
+    BlockId{ block_id: String },
+    Workspace{ workspace: bool },
+    #[serde(other)]
+    Unknown
+}
+
+impl Parent{
+    /// The ID of the parent page, database or block; `None` for the workspace.
+    pub fn id(&self) -> Option<&str>{
+        match self{
+            Parent::PageId{ page_id } => Some(page_id),
+            Parent::DatabaseId{ database_id } => Some(database_id),
+            Parent::BlockId{ block_id } => Some(block_id),
+            Parent::Workspace{ .. } | Parent::Unknown => None
+        }
+    }
+
+    pub fn parent_type(&self) -> &'static str{
+        match self{
+            Parent::PageId{ .. } => "page",
+            Parent::DatabaseId{ .. } => "database",
+            Parent::BlockId{ .. } => "block",
+            Parent::Workspace{ .. } => "workspace",
+            Parent::Unknown => "unknown"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExternalFile{
+    pub url: String
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HostedFile{
+    pub url: String,
+    #[serde(default)]
+    pub expiry_time: Option<String>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FileObject{
+    External{ external: ExternalFile },
+    File{ file: HostedFile },
+    #[serde(other)]
+    Unknown
+}
+
+impl FileObject{
+    pub fn url(&self) -> Option<&str>{
+        match self{
+            FileObject::External{ external } => Some(&external.url),
+            FileObject::File{ file } => Some(&file.url),
+            FileObject::Unknown => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Icon{
+    Emoji{ emoji: String },
+    External{ external: ExternalFile },
+    File{ file: HostedFile },
+    #[serde(other)]
+    Unknown
+}
+
+impl Icon{
+    /// The emoji itself, or the URL of an image icon.
+    pub fn as_text(&self) -> Option<&str>{
+        match self{
+            Icon::Emoji{ emoji } => Some(emoji),
+            Icon::External{ external } => Some(&external.url),
+            Icon::File{ file } => Some(&file.url),
+            Icon::Unknown => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TextBlock{
+    #[serde(default)]
+    pub rich_text: Vec<RichText>,
+    #[serde(default)]
+    pub color: Option<String>
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct HeadingBlock{
+    #[serde(default)]
+    pub rich_text: Vec<RichText>,
+    #[serde(default)]
+    pub is_toggleable: bool
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ToDoBlock{
+    #[serde(default)]
+    pub rich_text: Vec<RichText>,
+    #[serde(default)]
+    pub checked: bool
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CalloutBlock{
+    #[serde(default)]
+    pub rich_text: Vec<RichText>,
+    #[serde(default)]
+    pub icon: Option<Icon>
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CodeBlock{
+    #[serde(default)]
+    pub rich_text: Vec<RichText>,
+    #[serde(default)]
+    pub caption: Vec<RichText>,
+    #[serde(default)]
+    pub language: String
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TableBlock{
+    #[serde(default)]
+    pub table_width: u32,
+    #[serde(default)]
+    pub has_column_header: bool,
+    #[serde(default)]
+    pub has_row_header: bool
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TableRowBlock{
+    #[serde(default)]
+    pub cells: Vec<Vec<RichText>>
+}
+
+/// Shared shape of `bookmark`, `embed` and `link_preview` blocks.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UrlBlock{
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub caption: Vec<RichText>
+}
+
+/// Shared shape of `image`, `file`, `pdf`, `video` and `audio` blocks.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FileBlock{
+    #[serde(flatten)]
+    pub file: FileObject,
+    #[serde(default)]
+    pub caption: Vec<RichText>,
+    #[serde(default)]
+    pub name: Option<String>
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TitleBlock{
+    #[serde(default)]
+    pub title: String
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct LinkToPageBlock{
+    #[serde(default)]
+    pub page_id: Option<String>,
+    #[serde(default)]
+    pub database_id: Option<String>
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SyncedBlock{
+    #[serde(default)]
+    pub synced_from: Option<SyncedFrom>
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SyncedFrom{
+    #[serde(default)]
+    pub block_id: String
+}
+
+/// Blocks whose type-specific object carries no content we make use of.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct EmptyBlock{}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BlockType{
+    Paragraph{ paragraph: TextBlock },
+    #[serde(rename = "heading_1")]
+    Heading1{ heading_1: HeadingBlock },
+    #[serde(rename = "heading_2")]
+    Heading2{ heading_2: HeadingBlock },
+    #[serde(rename = "heading_3")]
+    Heading3{ heading_3: HeadingBlock },
+    BulletedListItem{ bulleted_list_item: TextBlock },
+    NumberedListItem{ numbered_list_item: TextBlock },
+    ToDo{ to_do: ToDoBlock },
+    Toggle{ toggle: TextBlock },
+    Quote{ quote: TextBlock },
+    Callout{ callout: CalloutBlock },
+    Code{ code: CodeBlock },
+    Divider{ divider: EmptyBlock },
+    Equation{ equation: EquationContent },
+    Table{ table: TableBlock },
+    TableRow{ table_row: TableRowBlock },
+    Bookmark{ bookmark: UrlBlock },
+    Embed{ embed: UrlBlock },
+    LinkPreview{ link_preview: UrlBlock },
+    Image{ image: FileBlock },
+    File{ file: FileBlock },
+    Pdf{ pdf: FileBlock },
+    Video{ video: FileBlock },
+    Audio{ audio: FileBlock },
+    ChildPage{ child_page: TitleBlock },
+    ChildDatabase{ child_database: TitleBlock },
+    LinkToPage{ link_to_page: LinkToPageBlock },
+    ColumnList{ column_list: EmptyBlock },
+    Column{ column: EmptyBlock },
+    SyncedBlock{ synced_block: SyncedBlock },
+    Template{ template: TextBlock },
+    Breadcrumb{ breadcrumb: EmptyBlock },
+    TableOfContents{ table_of_contents: EmptyBlock },
+    #[serde(other)]
+    Unknown
+}
+
+impl BlockType{
+    /// Returns the rich text of blocks that carry their text in a `rich_text` field.
+    pub fn rich_text(&self) -> Option<&[RichText]>{
+        match self{
+            BlockType::Paragraph{ paragraph: x }
+            | BlockType::BulletedListItem{ bulleted_list_item: x }
+            | BlockType::NumberedListItem{ numbered_list_item: x }
+            | BlockType::Toggle{ toggle: x }
+            | BlockType::Quote{ quote: x }
+            | BlockType::Template{ template: x } => Some(&x.rich_text),
+            BlockType::Heading1{ heading_1: x }
+            | BlockType::Heading2{ heading_2: x }
+            | BlockType::Heading3{ heading_3: x } => Some(&x.rich_text),
+            BlockType::ToDo{ to_do } => Some(&to_do.rich_text),
+            BlockType::Callout{ callout } => Some(&callout.rich_text),
+            BlockType::Code{ code } => Some(&code.rich_text),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Block{
+    pub id: String,
+    #[serde(default)]
+    pub parent: Option<Parent>,
+    #[serde(default)]
+    pub created_time: Option<String>,
+    #[serde(default)]
+    pub last_edited_time: Option<String>,
+    #[serde(default)]
+    pub created_by: Option<User>,
+    #[serde(default)]
+    pub last_edited_by: Option<User>,
+    #[serde(default)]
+    pub has_children: bool,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub in_trash: bool,
+    #[serde(flatten)]
+    pub block_type: BlockType
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Page{
+    pub id: String,
+    #[serde(default)]
+    pub created_time: Option<String>,
+    #[serde(default)]
+    pub last_edited_time: Option<String>,
+    #[serde(default)]
+    pub created_by: Option<User>,
+    #[serde(default)]
+    pub last_edited_by: Option<User>,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub in_trash: bool,
+    #[serde(default)]
+    pub icon: Option<Icon>,
+    #[serde(default)]
+    pub cover: Option<FileObject>,
+    #[serde(default)]
+    pub parent: Option<Parent>,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub public_url: Option<String>,
+    #[serde(default)]
+    pub properties: HashMap<String, PropertyValue>
+}
+
+impl User{
+    /// The user's name, or their ID for partial user objects that carry no name.
+    pub fn display_name(&self) -> &str{
+        return self.name.as_deref().unwrap_or(&self.id);
+    }
+}
+
+/// Descriptive fields of a page, used to link search results back to Notion.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PageMetadata{
+    pub page_id: String,
+    pub title: String,
+    pub url: String,
+    pub icon: Option<String>,
+    pub created_time: Option<String>,
+    pub last_edited_time: Option<String>,
+    pub created_by: Option<String>,
+    pub last_edited_by: Option<String>,
+    pub archived: bool,
+    pub parent_type: Option<String>,
+    pub parent_id: Option<String>
+}
+
+impl PageMetadata{
+    /// Flattens the metadata into string key/value pairs, skipping unset fields, for use
+    /// as Qdrant payload.
+    pub fn to_payload_map(&self) -> HashMap<String, String>{
+        let mut payload_map: HashMap<String, String> = HashMap::from([
+            ("page_id".to_string(), self.page_id.clone()),
+            ("title".to_string(), self.title.clone()),
+            ("url".to_string(), self.url.clone()),
+            ("archived".to_string(), self.archived.to_string())
+        ]);
+
+        let optional_fields = [
+            ("icon", &self.icon),
+            ("created_time", &self.created_time),
+            ("last_edited_time", &self.last_edited_time),
+            ("created_by", &self.created_by),
+            ("last_edited_by", &self.last_edited_by),
+            ("parent_type", &self.parent_type),
+            ("parent_id", &self.parent_id)
+        ];
+        for (field_name, field_value) in optional_fields{
+            if let Some(field_value) = field_value {
+                payload_map.insert(field_name.to_string(), field_value.clone());
+            }
+        }
+
+        return payload_map;
+    }
+}
+
+impl Page{
+    pub fn metadata(&self) -> PageMetadata{
+        return PageMetadata{
+            page_id: self.id.clone(),
+            title: self.title(),
+            url: self.url.clone(),
+            icon: self.icon.as_ref().and_then(|x| x.as_text()).map(|x| x.to_string()),
+            created_time: self.created_time.clone(),
+            last_edited_time: self.last_edited_time.clone(),
+            created_by: self.created_by.as_ref().map(|x| x.display_name().to_string()),
+            last_edited_by: self.last_edited_by.as_ref().map(|x| x.display_name().to_string()),
+            archived: self.archived || self.in_trash,
+            parent_type: self.parent.as_ref().map(|x| x.parent_type().to_string()),
+            parent_id: self.parent.as_ref().and_then(|x| x.id()).map(|x| x.to_string())
+        };
+    }
+
+    /// Returns the plain text of the page's `title` property.
+    pub fn title(&self) -> String{
+        for property in self.properties.values(){
+            if let PropertyValue::Title{ title } = property {
+                return plain_text(title);
+            }
+        }
+        return String::new();
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Database{
+    pub id: String,
+    #[serde(default)]
+    pub title: Vec<RichText>,
+    #[serde(default)]
+    pub description: Vec<RichText>,
+    #[serde(default)]
+    pub created_time: Option<String>,
+    #[serde(default)]
+    pub last_edited_time: Option<String>,
+    #[serde(default)]
+    pub created_by: Option<User>,
+    #[serde(default)]
+    pub last_edited_by: Option<User>,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub in_trash: bool,
+    #[serde(default)]
+    pub is_inline: bool,
+    #[serde(default)]
+    pub icon: Option<Icon>,
+    #[serde(default)]
+    pub parent: Option<Parent>,
+    #[serde(default)]
+    pub url: String,
+    /// The database schema, keyed by property name. Row values are found in the
+    /// `properties` of the pages returned by `NotionPagesAPI::query_database`.
+    #[serde(default)]
+    pub properties: HashMap<String, serde_json::Value>
+}
+
+impl Database{
+    pub fn title(&self) -> String{
+        return plain_text(&self.title);
+    }
+}
+
+/// An object returned by `POST /v1/search`, which mixes pages and databases.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "object", rename_all = "snake_case")]
+pub enum SearchResult{
+    Page(Page),
+    Database(Database),
+    #[serde(other)]
+    Unknown
+}
+
+impl SearchResult{
+    pub fn id(&self) -> Option<&str>{
+        match self{
+            SearchResult::Page(page) => Some(&page.id),
+            SearchResult::Database(database) => Some(&database.id),
+            SearchResult::Unknown => None
+        }
+    }
+
+    pub fn title(&self) -> String{
+        match self{
+            SearchResult::Page(page) => page.title(),
+            SearchResult::Database(database) => database.title(),
+            SearchResult::Unknown => String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn page_metadata_is_read_from_a_retrieved_page(){
+        let page: Page = serde_json::from_value(json!({
+            "object": "page",
+            "id": "59833787-2cf9-4fdf-8782-e53db20768a5",
+            "created_time": "2022-03-01T19:05:00.000Z",
+            "last_edited_time": "2022-07-06T20:25:00.000Z",
+            "created_by": { "object": "user", "id": "ee5f0f84-409a-440f-983a-a5315961c6e4" },
+            "last_edited_by": { "object": "user", "id": "0c3e9826-b8f7-4f73-927d-2caaf86f1103", "type": "person", "name": "Ada", "person": { "email": "ada@example.com" } },
+            "cover": { "type": "external", "external": { "url": "https://upload.wikimedia.org/cover.jpg" } },
+            "icon": { "type": "emoji", "emoji": "🥬" },
+            "parent": { "type": "database_id", "database_id": "d9824bdc-8445-4327-be8b-5b47500af6ce" },
+            "archived": false,
+            "in_trash": true,
+            "properties": {
+                "Name": { "id": "title", "type": "title", "title": [{ "type": "text", "text": { "content": "Tuscan kale", "link": null }, "plain_text": "Tuscan kale", "href": null }] }
+            },
+            "url": "https://www.notion.so/Tuscan-kale-598337872cf94fdf8782e53db20768a5",
+            "public_url": null
+        })).unwrap();
+
+        let metadata = page.metadata();
+        assert_eq!(metadata.title, "Tuscan kale");
+        assert_eq!(metadata.icon.as_deref(), Some("🥬"));
+        assert_eq!(metadata.created_by.as_deref(), Some("ee5f0f84-409a-440f-983a-a5315961c6e4"));
+        assert_eq!(metadata.last_edited_by.as_deref(), Some("Ada"));
+        assert_eq!(metadata.parent_type.as_deref(), Some("database"));
+        assert_eq!(metadata.parent_id.as_deref(), Some("d9824bdc-8445-4327-be8b-5b47500af6ce"));
+        assert!(metadata.archived, "pages in the trash count as archived");
+        assert_eq!(metadata.to_payload_map().get("icon").map(|x| x.as_str()), Some("🥬"));
+    }
+
+    #[test]
+    fn custom_emoji_icons_do_not_break_parsing(){
+        let page: Page = serde_json::from_value(json!({
+            "object": "page",
+            "id": "page-id",
+            "icon": { "type": "custom_emoji", "custom_emoji": { "id": "45ce454c-d427-4f53-9489-e5d0f3d1db6b", "name": "bufo", "url": "https://s3-us-west-2.amazonaws.com/public.notion-static.com/bufo.png" } },
+            "properties": {}
+        })).unwrap();
+
+        assert!(matches!(page.icon, Some(Icon::Unknown)));
+        assert_eq!(page.metadata().icon, None);
+        assert!(!page.metadata().to_payload_map().contains_key("icon"));
+    }
+
+    #[test]
+    fn unknown_types_fall_back_instead_of_failing(){
+        let block: Block = serde_json::from_value(json!({
+            "object": "block",
+            "id": "block-id",
+            "parent": { "type": "space_id", "space_id": "space" },
+            "type": "transcription",
+            "transcription": { "title": [] },
+            "has_children": true
+        })).unwrap();
+        assert!(matches!(block.block_type, BlockType::Unknown));
+        assert!(matches!(block.parent, Some(Parent::Unknown)));
+        assert!(block.has_children);
+
+        let rich_text: RichText = serde_json::from_value(json!({
+            "type": "template_mention", "template_mention": { "type": "template_mention_date" }, "plain_text": "@Today"
+        })).unwrap();
+        assert!(matches!(rich_text.kind, RichTextKind::Unknown));
+        assert_eq!(rich_text.plain_text, "@Today");
+
+        let file: FileObject = serde_json::from_value(json!({ "type": "file_upload", "file_upload": { "id": "upload-id" } })).unwrap();
+        assert!(matches!(file, FileObject::Unknown));
+        assert_eq!(file.url(), None);
+    }
+
+    #[test]
+    fn search_results_mix_pages_databases_and_unknown_objects(){
+        let search_results: PaginatedList<SearchResult> = serde_json::from_value(json!({
+            "object": "list",
+            "results": [
+                { "object": "page", "id": "page-id", "properties": { "title": { "type": "title", "title": [{ "type": "text", "plain_text": "Recipes", "text": { "content": "Recipes" } }] } } },
+                { "object": "database", "id": "database-id", "title": [{ "type": "text", "plain_text": "Groceries", "text": { "content": "Groceries" } }], "properties": { "Name": { "id": "title", "name": "Name", "type": "title", "title": {} } } },
+                { "object": "data_source", "id": "data-source-id" }
+            ],
+            "next_cursor": "cursor-2",
+            "has_more": false,
+            "type": "page_or_database",
+            "page_or_database": {}
+        })).unwrap();
+
+        let (results, next_cursor) = search_results.into_page();
+        assert_eq!(next_cursor, None, "the cursor only counts while has_more is set");
+        let titles: Vec<(Option<&str>, String)> = results.iter().map(|x| (x.id(), x.title())).collect();
+        assert_eq!(titles, vec![
+            (Some("page-id"), "Recipes".to_string()),
+            (Some("database-id"), "Groceries".to_string()),
+            (None, String::new())
+        ]);
+    }
+}
@@ -0,0 +1,52 @@
+//! Deterministic Qdrant point IDs for the chunks of Notion pages.
+//!
+//! A chunk's ID is a UUIDv5 of the Notion page (or block) ID and the chunk's index, so
+//! re-indexing a page overwrites its points in place, pages with the same title never
+//! collide, and renaming a page keeps its IDs.
+
+use uuid::Uuid;
+use crate::notion_types::normalize_notion_id;
+
+/// Namespace of every point ID, the UUIDv5 of the project URL under `NAMESPACE_URL`.
+pub const POINT_ID_NAMESPACE: Uuid = Uuid::from_u128(0xbbe011b3_1202_59f5_89fd_3900515ccde6);
+
+/// The ID of chunk `chunk_index` of the page or block `notion_id`, the same whichever
+/// form of the Notion ID is given.
+pub fn chunk_point_id(notion_id: &str, chunk_index: usize) -> Uuid{
+    let id_name = format!("{}:{}", normalize_notion_id(notion_id), chunk_index);
+    return Uuid::new_v5(&POINT_ID_NAMESPACE, id_name.as_bytes());
+}
+
+/// The IDs of all chunks of a page split into `chunk_count` chunks, in chunk order.
+pub fn page_point_ids(page_id: &str, chunk_count: usize) -> Vec<Uuid>{
+    return (0..chunk_count).map(|x| chunk_point_id(page_id, x)).collect();
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn point_ids_ignore_the_form_of_the_notion_id(){
+        let dashed_id = "59833787-2cf9-4fdf-8782-e53db20768a5";
+        assert_eq!(chunk_point_id(dashed_id, 0), chunk_point_id("598337872cf94fdf8782e53db20768a5", 0));
+        assert_eq!(chunk_point_id(dashed_id, 0), chunk_point_id(&dashed_id.to_uppercase(), 0));
+    }
+
+    #[test]
+    fn point_ids_are_stable_across_runs(){
+        // Changing these would orphan every point indexed so far.
+        assert_eq!(chunk_point_id("59833787-2cf9-4fdf-8782-e53db20768a5", 0).to_string(), "01bd203d-fdc3-59ee-9a21-620a43cbd402");
+        assert_eq!(chunk_point_id("59833787-2cf9-4fdf-8782-e53db20768a5", 3).to_string(), "ffdd57b3-29b3-54b0-9e37-2832a6eeb465");
+    }
+
+    #[test]
+    fn every_chunk_gets_its_own_point_id(){
+        let point_ids = page_point_ids("59833787-2cf9-4fdf-8782-e53db20768a5", 50);
+        let unique_point_ids: std::collections::HashSet<&Uuid> = point_ids.iter().collect();
+
+        assert_eq!(unique_point_ids.len(), 50);
+        assert_eq!(point_ids[7], chunk_point_id("59833787-2cf9-4fdf-8782-e53db20768a5", 7));
+        assert_ne!(page_point_ids("other-page", 1)[0], point_ids[0]);
+    }
+}
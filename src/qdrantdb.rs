@@ -2,31 +2,76 @@ use qdrant_client::client::QdrantClient;
 use qdrant_client::prelude::*;
 use qdrant_client::qdrant::vectors_config::Config;
 use qdrant_client::qdrant::{
-    PointStruct, Condition, CreateCollection, Filter, SearchPoints, VectorParams, VectorsConfig
+    PointId, PointStruct, Condition, CreateCollection, Filter, SearchPoints, VectorParams, VectorsConfig
 };
 use uuid::Uuid;
-use crate::fast_embed::{FastEmbedStruct};
+use crate::embedder::{Embedder, EmbeddingError};
+use crate::fast_embed::{FastEmbedStruct, DEFAULT_EMBEDDING_BATCH_SIZE};
 
 use std::collections::HashMap;
+use std::sync::Arc;
 
 pub struct QdrantDBStruct{
     client: QdrantClient,
-    pub embeddings_model: FastEmbedStruct
+    /// Shared so embedding can run on blocking worker threads.
+    pub embeddings_model: Arc<dyn Embedder>,
+    embedding_batch_size: usize
+}
+
+/// Errors of operations on a collection and its points.
+#[derive(Debug, thiserror::Error)]
+pub enum QdrantError{
+    #[error("Qdrant request on collection {collection} failed: {source}")]
+    Request{ collection: String, source: anyhow::Error },
+
+    #[error("failed to embed documents for collection {collection}: {source}")]
+    Embedding{ collection: String, source: EmbeddingError },
+
+    #[error("invalid request on collection {collection}: {message}")]
+    InvalidRequest{ collection: String, message: String }
+}
+
+/// A search result together with the payload stored alongside it, e.g. the `url` and
+/// `title` of the Notion page the document came from.
+#[derive(Debug, Clone)]
+pub struct SearchHit{
+    pub document: String,
+    pub score: f64,
+    pub payload: HashMap<String, String>
 }
 
 
 impl QdrantDBStruct{
 
-    pub fn new(vectordb_url: Option<&str>, embeddings_model_name: Option<&str>) -> Self{
-        log::info!("Initializing new instance for QdrantDB");
+    /// Fails if the embedding model name is unknown or the model cannot be loaded.
+    pub fn new(vectordb_url: Option<&str>, embeddings_model_name: Option<&str>) -> Result<Self, EmbeddingError>{
+        let embeddings_model = FastEmbedStruct::new(embeddings_model_name)?;
+        return Ok(Self::new_with_embedder(vectordb_url, Arc::new(embeddings_model)));
+    }
+
+    /// Uses any `Embedder`, e.g. a `HashEmbedder` to run without downloading a model.
+    pub fn new_with_embedder(vectordb_url: Option<&str>, embeddings_model: Arc<dyn Embedder>) -> Self{
+        log::info!("Initializing new instance for QdrantDB with embedder {}", embeddings_model.model_id());
         let default_qdrant_url = "http://localhost:6334".to_string();
 
         return QdrantDBStruct{
             client: QdrantClient::from_url(vectordb_url.unwrap_or(&default_qdrant_url)).build().unwrap(),
-            embeddings_model: FastEmbedStruct::new(embeddings_model_name)
+            embeddings_model,
+            embedding_batch_size: DEFAULT_EMBEDDING_BATCH_SIZE
         };
     }
 
+    /// How many documents are embedded and upserted together when adding to a
+    /// collection.
+    pub fn embedding_batch_size(mut self, embedding_batch_size: usize) -> Self{
+        self.embedding_batch_size = embedding_batch_size.max(1);
+        return self;
+    }
+
+    pub fn get_embedding_batch_size(&self) -> usize{
+        return self.embedding_batch_size;
+    }
+
     pub async fn list_available_collections(&self){
         log::info!("Listing available collections");
         let list_collection_response = self.client.list_collections().await.unwrap();
@@ -51,8 +96,10 @@ impl QdrantDBStruct{
         log::debug!("Qdrant's Time taken:: for deleting collection {} is {}", collection_to_delete, delete_collection_response.time);
     }
 
-    pub async fn create_collection(&self, collection_name: &str){
-        if self.client.has_collection(collection_name).await.unwrap(){
+    pub async fn create_collection(&self, collection_name: &str) -> Result<(), QdrantError>{
+        let collection_exists = self.client.has_collection(collection_name).await
+            .map_err(|source| QdrantError::Request{ collection: collection_name.to_string(), source })?;
+        if collection_exists{
             log::warn!("Collection name {} already exists!", collection_name);
         }
         else{
@@ -62,15 +109,16 @@ impl QdrantDBStruct{
                 collection_name: collection_name.to_string(),
                 vectors_config: Some(VectorsConfig {
                     config: Some(Config::Params(VectorParams {
-                        size: self.embeddings_model.get_current_model_size(),
+                        size: self.embeddings_model.dimension(),
                         distance: Distance::Cosine.into(),
                         ..Default::default()
                     })),
                 }),
                 ..Default::default()
             })
-            .await.unwrap();
-            
+            .await
+            .map_err(|source| QdrantError::Request{ collection: collection_name.to_string(), source })?;
+
             match create_collection_response.result{
                 true => {
                     log::info!("Collection {} creation Successful", collection_name);
@@ -81,51 +129,171 @@ impl QdrantDBStruct{
             }
 
             log::debug!("Qdrant's Time taken:: for creating collection {} is {}", collection_name, create_collection_response.time);
-        }    
+        }
+        return Ok(());
     }
 
-    pub async fn add_stuff_to_collection(&self, collection_name: &str, stuff_to_add: Vec<String>, id_for_stuff: Vec<Uuid>, metadata_for_stuff: Vec<HashMap<String, String>>){
-        
-        if !(stuff_to_add.len() == id_for_stuff.len() && id_for_stuff.len() == metadata_for_stuff.len()) {
-            log::error!("Vectors should be of same length!");
-            return;
+    pub async fn add_stuff_to_collection(&self, collection_name: &str, stuff_to_add: Vec<String>, id_for_stuff: Vec<Uuid>, metadata_for_stuff: Vec<HashMap<String, String>>) -> Result<(), QdrantError>{
+        let payload_for_stuff: Vec<Payload> = metadata_for_stuff.into_iter().map(Self::create_payload_data).collect();
+
+        return self.add_stuff_with_payload_to_collection(collection_name, stuff_to_add, id_for_stuff, payload_for_stuff).await;
+    }
+
+    /// Same as `add_stuff_to_collection`, but with a ready-made payload per document so
+    /// that typed values (numbers, lists, booleans...) can be stored as is.
+    pub async fn add_stuff_with_payload_to_collection(&self, collection_name: &str, stuff_to_add: Vec<String>, id_for_stuff: Vec<Uuid>, payload_for_stuff: Vec<Payload>) -> Result<(), QdrantError>{
+
+        if !(stuff_to_add.len() == id_for_stuff.len() && id_for_stuff.len() == payload_for_stuff.len()) {
+            return Err(QdrantError::InvalidRequest{
+                collection: collection_name.to_string(),
+                message: format!("got {} documents, {} IDs and {} payloads", stuff_to_add.len(), id_for_stuff.len(), payload_for_stuff.len())
+            });
         }
 
-        log::info!("Adding data to collection {}", collection_name);
+        log::info!("Adding {} documents to collection {} in batches of {}", stuff_to_add.len(), collection_name, self.embedding_batch_size);
+
+        // Each batch is upserted as soon as it is embedded, so large inputs never have
+        // all of their vectors in memory at once.
+        let mut stuff_iter = stuff_to_add.into_iter();
+        let mut id_iter = id_for_stuff.into_iter();
+        let mut payload_iter = payload_for_stuff.into_iter();
+        loop {
+            let batch_stuff: Vec<String> = stuff_iter.by_ref().take(self.embedding_batch_size).collect();
+            if batch_stuff.is_empty() {
+                break;
+            }
+            let batch_ids: Vec<Uuid> = id_iter.by_ref().take(batch_stuff.len()).collect();
+            let batch_payloads: Vec<Payload> = payload_iter.by_ref().take(batch_stuff.len()).collect();
+
+            // Batches upserted before a failing one stay in the collection.
+            let converted_vectors = QdrantDBStruct::convert_to_pointstruct(self.embeddings_model.as_ref(), batch_stuff, batch_ids, batch_payloads, self.embedding_batch_size)
+                .map_err(|source| QdrantError::Embedding{ collection: collection_name.to_string(), source })?;
+            let add_to_collection_response = self.client.upsert_points_blocking(collection_name, None, converted_vectors, None).await
+                .map_err(|source| QdrantError::Request{ collection: collection_name.to_string(), source })?;
+            log::info!("Add stuff to collection {} response: {:?}", collection_name, add_to_collection_response.result);
 
-        let converted_vectors = QdrantDBStruct::convert_to_pointstruct(&self.embeddings_model, stuff_to_add, id_for_stuff, metadata_for_stuff);
-        // let add_to_collection_response = self.client.upsert_points_batch_blocking(collection_name, converted_vectors, None, 100).await.unwrap();
-        let add_to_collection_response = self.client.upsert_points_batch_blocking(collection_name, None, converted_vectors, None, 100).await.unwrap();
-        log::info!("Add stuff to collection {} response: {:?}", collection_name, add_to_collection_response.result);
-           
-        log::debug!("Qdrant's Time taken:: for adding stuff to collection {} is {}", collection_name, add_to_collection_response.time);
+            log::debug!("Qdrant's Time taken:: for adding stuff to collection {} is {}", collection_name, add_to_collection_response.time);
+        }
+        return Ok(());
     }
 
-    pub async fn search_collection(&self, collection_name: &str, search_query: &str, search_filter: Option<HashMap<&str, &str>>, search_limit: u64) -> HashMap<String, f64>{
+    /// Upserts documents whose embeddings were already computed, e.g. on a worker pool.
+    pub async fn add_embedded_stuff_to_collection(&self, collection_name: &str, stuff_to_add: Vec<String>, embeddings_for_stuff: Vec<Vec<f32>>, id_for_stuff: Vec<Uuid>, payload_for_stuff: Vec<Payload>) -> Result<(), QdrantError>{
+        if !(stuff_to_add.len() == embeddings_for_stuff.len() && stuff_to_add.len() == id_for_stuff.len() && id_for_stuff.len() == payload_for_stuff.len()) {
+            return Err(QdrantError::InvalidRequest{
+                collection: collection_name.to_string(),
+                message: format!(
+                    "got {} documents, {} embeddings, {} IDs and {} payloads",
+                    stuff_to_add.len(), embeddings_for_stuff.len(), id_for_stuff.len(), payload_for_stuff.len()
+                )
+            });
+        }
+        if stuff_to_add.is_empty() {
+            return Ok(());
+        }
+
+        let converted_vectors = Self::create_point_structs(stuff_to_add, embeddings_for_stuff, id_for_stuff, payload_for_stuff);
+        let add_to_collection_response = self.client.upsert_points_blocking(collection_name, None, converted_vectors, None).await
+            .map_err(|source| QdrantError::Request{ collection: collection_name.to_string(), source })?;
+        log::info!("Add embedded stuff to collection {} response: {:?}", collection_name, add_to_collection_response.result);
+
+        log::debug!("Qdrant's Time taken:: for adding embedded stuff to collection {} is {}", collection_name, add_to_collection_response.time);
+        return Ok(());
+    }
+
+    pub async fn delete_points(&self, collection_name: &str, ids_to_delete: Vec<Uuid>) -> Result<(), QdrantError>{
+        if ids_to_delete.is_empty() {
+            return Ok(());
+        }
+
+        log::info!("Deleting {} points from collection {}", ids_to_delete.len(), collection_name);
+
+        let point_ids: Vec<PointId> = ids_to_delete.iter().map(|x| x.to_string().into()).collect();
+        let delete_points_response = self.client.delete_points_blocking(collection_name, None, &point_ids.into(), None).await
+            .map_err(|source| QdrantError::Request{ collection: collection_name.to_string(), source })?;
+        log::info!("Delete points from collection {} response: {:?}", collection_name, delete_points_response.result);
+
+        log::debug!("Qdrant's Time taken:: for deleting points from collection {} is {}", collection_name, delete_points_response.time);
+        return Ok(());
+    }
+
+    /// Deletes every point whose payload matches all of `delete_filter`, e.g.
+    /// `{"page_id": "..."}` for everything indexed from one Notion page.
+    pub async fn delete_points_by_filter(&self, collection_name: &str, delete_filter: HashMap<&str, &str>) -> Result<(), QdrantError>{
+        if delete_filter.is_empty() {
+            // An empty filter matches every point; use `delete_collection` for that.
+            return Err(QdrantError::InvalidRequest{ collection: collection_name.to_string(), message: "refusing to delete points with an empty filter".to_string() });
+        }
+
+        let qdrant_filter = match QdrantDBStruct::create_query_filter("all", delete_filter) {
+            Some(qdrant_filter) => qdrant_filter,
+            None => return Err(QdrantError::InvalidRequest{ collection: collection_name.to_string(), message: "invalid delete filter".to_string() })
+        };
+
+        log::info!("Deleting points matching filter from collection {}", collection_name);
+
+        let delete_points_response = self.client.delete_points_blocking(collection_name, None, &qdrant_filter.into(), None).await
+            .map_err(|source| QdrantError::Request{ collection: collection_name.to_string(), source })?;
+        log::info!("Delete points by filter from collection {} response: {:?}", collection_name, delete_points_response.result);
+
+        log::debug!("Qdrant's Time taken:: for deleting points by filter from collection {} is {}", collection_name, delete_points_response.time);
+        return Ok(());
+    }
+
+    pub async fn search_collection(&self, collection_name: &str, search_query: &str, search_filter: Option<HashMap<&str, &str>>, search_limit: u64) -> Result<HashMap<String, f64>, QdrantError>{
+        let search_hits = self.search_collection_with_payload(collection_name, search_query, search_filter, search_limit).await?;
+
+        let output_hashmap : HashMap<String, f64> = search_hits.into_iter().map(|x| (x.document, x.score)).collect();
+
+        return Ok(output_hashmap);
+    }
+
+    /// Like `search_collection`, but keeps the payload of every hit so results can link
+    /// back to their source page.
+    pub async fn search_collection_with_payload(&self, collection_name: &str, search_query: &str, search_filter: Option<HashMap<&str, &str>>, search_limit: u64) -> Result<Vec<SearchHit>, QdrantError>{
         
-        let vec_to_search = &self.embeddings_model.embed_stuff(vec![search_query.to_string()])[0];
+        // Queries go through the query path so models like BGE and E5 get their query prefix.
+        let vec_to_search = self.embeddings_model.embed_query(search_query)
+            .map_err(|source| QdrantError::Embedding{ collection: collection_name.to_string(), source })?;
 
         let mut qdrant_filter = None;
-        if search_filter.is_some() {
-            qdrant_filter = QdrantDBStruct::create_query_filter("all", search_filter.unwrap());
+        if let Some(search_filter) = search_filter {
+            qdrant_filter = QdrantDBStruct::create_query_filter("all", search_filter);
         }
 
         let search_result_response = self.client.search_points(&SearchPoints {
             collection_name: collection_name.to_string(),
-            vector: vec_to_search.clone(),
+            vector: vec_to_search,
             filter: qdrant_filter,
             limit: search_limit,
             with_payload: Some(true.into()),
             ..Default::default()
         })
-        .await.unwrap();
+        .await
+        .map_err(|source| QdrantError::Request{ collection: collection_name.to_string(), source })?;
         
-        let output_hashmap : HashMap<String, f64> = search_result_response.result.iter().map(|x| {
-            let without_quotes = x.payload["document_for_embeddings"].as_str().unwrap().trim_matches('"').to_string();
-            return (without_quotes, x.score.into());
+        let search_hits: Vec<SearchHit> = search_result_response.result.iter().map(|x| {
+            // Points written by other tools may lack the document.
+            let without_quotes = x.payload.get("document_for_embeddings").and_then(|x| x.as_str()).map(|x| x.trim_matches('"').to_string()).unwrap_or_default();
+            let payload: HashMap<String, String> = x.payload.iter()
+                .filter(|(payload_key, _)| payload_key.as_str() != "document_for_embeddings")
+                .map(|(payload_key, payload_value)| {
+                    let payload_text = match payload_value.as_str() {
+                        Some(payload_str) => payload_str.to_string(),
+                        None => payload_value.to_string()
+                    };
+                    return (payload_key.clone(), payload_text);
+                })
+                .collect();
+
+            return SearchHit{
+                document: without_quotes,
+                score: x.score.into(),
+                payload
+            };
         }).collect();
 
-        return output_hashmap;
+        return Ok(search_hits);
     }
 
     fn create_empty_payload() -> Payload{
@@ -138,7 +306,7 @@ impl QdrantDBStruct{
         return payload_to_add;
     }
 
-    fn create_payload_data(payload_map: HashMap<String, String>) -> Payload{
+    pub fn create_payload_data(payload_map: HashMap<String, String>) -> Payload{
         let mut tmp_payload = Self::create_empty_payload();
         for (payload_key, payload_value) in payload_map.iter(){
             tmp_payload.insert(payload_key.to_string(), payload_value.to_string());
@@ -151,15 +319,20 @@ impl QdrantDBStruct{
         return PointStruct::new(id_num.to_string(), embeddings_data, payload_data);
     }
 
-    fn convert_to_pointstruct(embeddings_model: &FastEmbedStruct, doc_to_pointstruct: Vec<String>, id_for_pointstruct: Vec<Uuid>, metadata_for_pointstruct: Vec<HashMap<String, String>> ) -> Vec<PointStruct>{
-        let mut tmp_vector_store: Vec<PointStruct> = Vec::new();
-        for doc_idx in 0..doc_to_pointstruct.len(){
-            let embeddings_data = &embeddings_model.embed_stuff(vec![doc_to_pointstruct[doc_idx].clone()])[0];
-            let mut payload_data = Self::create_payload_data(metadata_for_pointstruct[doc_idx].clone());
+    /// Embeds all documents in batches of `batch_size` and pairs every vector with its
+    /// ID and payload.
+    fn convert_to_pointstruct(embeddings_model: &dyn Embedder, doc_to_pointstruct: Vec<String>, id_for_pointstruct: Vec<Uuid>, payload_for_pointstruct: Vec<Payload>, batch_size: usize) -> Result<Vec<PointStruct>, EmbeddingError>{
+        let embeddings_data = embeddings_model.embed_documents(doc_to_pointstruct.clone(), Some(batch_size))?;
+
+        return Ok(Self::create_point_structs(doc_to_pointstruct, embeddings_data, id_for_pointstruct, payload_for_pointstruct));
+    }
+
+    fn create_point_structs(doc_to_pointstruct: Vec<String>, embeddings_data: Vec<Vec<f32>>, id_for_pointstruct: Vec<Uuid>, payload_for_pointstruct: Vec<Payload>) -> Vec<PointStruct>{
+        let mut tmp_vector_store: Vec<PointStruct> = Vec::with_capacity(doc_to_pointstruct.len());
+        for (((doc_data, embedding_data), id_data), payload_data) in doc_to_pointstruct.iter().zip(embeddings_data).zip(id_for_pointstruct).zip(payload_for_pointstruct){
             // add document as payload
-            payload_data = Self::add_to_existing_payload(payload_data, "document_for_embeddings", &doc_to_pointstruct[doc_idx]);
-            let id_data = id_for_pointstruct[doc_idx];
-            tmp_vector_store.push(Self::create_point_struct(id_data.into(), embeddings_data.clone(), payload_data));
+            let payload_data = Self::add_to_existing_payload(payload_data, "document_for_embeddings", doc_data);
+            tmp_vector_store.push(Self::create_point_struct(id_data, embedding_data, payload_data));
         }
         return tmp_vector_store;
     }
@@ -192,6 +365,7 @@ impl QdrantDBStruct{
         
     }
 
+    #[deprecated(note = "title based IDs collide across pages; use `point_ids::chunk_point_id` or `point_ids::page_point_ids`")]
     pub fn create_ids(text_vec_for_uuid: Vec<String>) -> Vec<Uuid>{
 
         let uuid_list: Vec<Uuid> = text_vec_for_uuid.iter().map(|x| {
@@ -202,4 +376,43 @@ impl QdrantDBStruct{
         return uuid_list;
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::embedder::HashEmbedder;
+    use qdrant_client::qdrant::vectors::VectorsOptions;
+
+    /// Embedding and building points needs no Qdrant server and, with a `HashEmbedder`,
+    /// no model either.
+    #[test]
+    fn points_are_built_offline_with_a_hash_embedder(){
+        let qdrant_db = QdrantDBStruct::new_with_embedder(None, Arc::new(HashEmbedder::new(16))).embedding_batch_size(1);
+        let documents = vec!["Pasta carbonara".to_string(), "Chocolate cake".to_string()];
+        let point_ids = vec![Uuid::from_u128(1), Uuid::from_u128(2)];
+        let mut page_payload = Payload::new();
+        page_payload.insert("page_id", "page");
+
+        let points = QdrantDBStruct::convert_to_pointstruct(
+            qdrant_db.embeddings_model.as_ref(),
+            documents.clone(),
+            point_ids.clone(),
+            vec![page_payload.clone(), page_payload],
+            qdrant_db.get_embedding_batch_size()
+        ).unwrap();
+
+        assert_eq!(points.len(), 2);
+        for ((point, document), point_id) in points.iter().zip(documents.iter()).zip(point_ids){
+            assert_eq!(point.id, Some(PointId::from(point_id.to_string())));
+            assert_eq!(point.payload["document_for_embeddings"].as_str().map(|x| x.as_str()), Some(document.as_str()));
+            assert_eq!(point.payload["page_id"].as_str().map(|x| x.as_str()), Some("page"));
+
+            let expected_embedding = qdrant_db.embeddings_model.embed_documents(vec![document.clone()], None).unwrap().remove(0);
+            match point.vectors.as_ref().and_then(|x| x.vectors_options.as_ref()) {
+                Some(VectorsOptions::Vector(vector)) => assert_eq!(vector.data, expected_embedding),
+                other => panic!("expected a single unnamed vector, got {:?}", other)
+            }
+        }
+        assert_eq!(qdrant_db.embeddings_model.dimension(), 16);
+    }
+}
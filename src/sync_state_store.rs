@@ -0,0 +1,336 @@
+//! Local, file-backed record of what has been indexed into Qdrant.
+//!
+//! The store is a single JSON file holding, per collection, one record per Notion page
+//! ID with the embedding model, content hash and point IDs used when the page was last
+//! indexed. The ingestion pipeline consults it to skip unchanged pages and to know which
+//! points to delete.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Where the sync state is kept when no other path is configured.
+pub const DEFAULT_SYNC_STATE_PATH: &str = "notion_sync_state.json";
+
+/// Bumped whenever the file layout changes incompatibly.
+const SYNC_STATE_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SyncStateError{
+    #[error("failed to read or write sync state {path:?}: {source}")]
+    Io{ path: PathBuf, source: std::io::Error },
+
+    #[error("failed to decode sync state {path:?}: {source}")]
+    Decode{ path: PathBuf, source: serde_json::Error },
+
+    #[error("failed to encode sync state for {path:?}: {source}")]
+    Encode{ path: PathBuf, source: serde_json::Error },
+
+    #[error("sync state {path:?} has version {found}, expected {expected}")]
+    UnsupportedVersion{ path: PathBuf, found: u32, expected: u32 }
+}
+
+/// What was indexed for a single page during the last successful sync.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct IndexedPageRecord{
+    pub page_id: String,
+    pub title: String,
+    pub embedding_model: String,
+    pub last_edited_time: Option<String>,
+    pub content_hash: String,
+    /// Hash of what is indexed besides the content: title, path, properties and chunking
+    /// strategy. Empty for records written before it was tracked.
+    #[serde(default)]
+    pub settings_hash: String,
+    pub point_ids: Vec<Uuid>,
+    /// Unix timestamp (seconds) of when the page was last written to Qdrant.
+    pub indexed_at: u64,
+    /// Unix timestamp (seconds) of when the recorded content was fetched from Notion. `0`
+    /// for records written before it was tracked.
+    #[serde(default)]
+    pub fetched_at: u64
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct StoredState{
+    version: u32,
+    /// Collection name -> page ID -> record.
+    collections: BTreeMap<String, BTreeMap<String, IndexedPageRecord>>
+}
+
+impl Default for StoredState{
+    fn default() -> Self{
+        return StoredState{
+            version: SYNC_STATE_VERSION,
+            collections: BTreeMap::new()
+        };
+    }
+}
+
+/// Persistent sync state, loaded fully into memory and written back on `save`.
+#[derive(Debug)]
+pub struct SyncStateStore{
+    state_path: PathBuf,
+    state: StoredState
+}
+
+impl SyncStateStore{
+    /// Opens the store at `state_path`, starting empty if the file does not exist yet.
+    pub fn open(state_path: impl Into<PathBuf>) -> Result<Self, SyncStateError>{
+        let state_path: PathBuf = state_path.into();
+
+        if !state_path.exists() {
+            log::info!("No sync state found at {:?}, starting from scratch", state_path);
+            return Ok(SyncStateStore{ state_path, state: StoredState::default() });
+        }
+
+        let state_json = std::fs::read_to_string(&state_path).map_err(|source| SyncStateError::Io{ path: state_path.clone(), source })?;
+        let state: StoredState = serde_json::from_str(&state_json).map_err(|source| SyncStateError::Decode{ path: state_path.clone(), source })?;
+
+        if state.version != SYNC_STATE_VERSION {
+            return Err(SyncStateError::UnsupportedVersion{ path: state_path, found: state.version, expected: SYNC_STATE_VERSION });
+        }
+
+        return Ok(SyncStateStore{ state_path, state });
+    }
+
+    pub fn path(&self) -> &Path{
+        return &self.state_path;
+    }
+
+    pub fn get(&self, collection_name: &str, page_id: &str) -> Option<&IndexedPageRecord>{
+        return self.state.collections.get(collection_name)?.get(page_id);
+    }
+
+    /// Inserts or replaces the record of `record.page_id`, returning the previous one.
+    pub fn upsert(&mut self, collection_name: &str, record: IndexedPageRecord) -> Option<IndexedPageRecord>{
+        return self.state.collections.entry(collection_name.to_string()).or_default().insert(record.page_id.clone(), record);
+    }
+
+    pub fn remove(&mut self, collection_name: &str, page_id: &str) -> Option<IndexedPageRecord>{
+        return self.state.collections.get_mut(collection_name)?.remove(page_id);
+    }
+
+    /// Every record of `collection_name`, ordered by page ID.
+    pub fn pages(&self, collection_name: &str) -> Vec<&IndexedPageRecord>{
+        match self.state.collections.get(collection_name) {
+            Some(collection_pages) => collection_pages.values().collect(),
+            None => Vec::new()
+        }
+    }
+
+    pub fn collection_names(&self) -> Vec<&String>{
+        return self.state.collections.keys().collect();
+    }
+
+    /// Forgets everything recorded for `collection_name`. The points in Qdrant are left
+    /// untouched, so the next sync re-indexes every page of the collection.
+    pub fn reset_collection(&mut self, collection_name: &str) -> usize{
+        match self.state.collections.remove(collection_name) {
+            Some(collection_pages) => collection_pages.len(),
+            None => 0
+        }
+    }
+
+    /// Forgets everything recorded for every collection.
+    pub fn reset(&mut self) -> usize{
+        let page_count = self.state.collections.values().map(|x| x.len()).sum();
+        self.state.collections.clear();
+        return page_count;
+    }
+
+    /// Writes the state to a temporary file first so an interrupted write never leaves
+    /// a truncated state file behind.
+    pub fn save(&self) -> Result<(), SyncStateError>{
+        let state_json = serde_json::to_string_pretty(&self.state).map_err(|source| SyncStateError::Encode{ path: self.state_path.clone(), source })?;
+
+        let temporary_path = self.state_path.with_extension("json.tmp");
+        std::fs::write(&temporary_path, state_json).map_err(|source| SyncStateError::Io{ path: temporary_path.clone(), source })?;
+        std::fs::rename(&temporary_path, &self.state_path).map_err(|source| SyncStateError::Io{ path: self.state_path.clone(), source })?;
+
+        return Ok(());
+    }
+
+    /// A human readable summary of the store, one line per page.
+    pub fn describe(&self) -> String{
+        let mut description = format!("Sync state at {:?}\n", self.state_path);
+
+        if self.state.collections.is_empty() {
+            description.push_str("  (empty)\n");
+        }
+
+        for (collection_name, collection_pages) in self.state.collections.iter(){
+            description.push_str(&format!("Collection {} ({} pages)\n", collection_name, collection_pages.len()));
+            for record in collection_pages.values(){
+                description.push_str(&format!(
+                    "  {} {:?} model={} points={} hash={} last_edited={} indexed_at={}\n",
+                    record.page_id,
+                    record.title,
+                    record.embedding_model,
+                    record.point_ids.len(),
+                    record.content_hash.get(..12).unwrap_or(&record.content_hash),
+                    record.last_edited_time.as_deref().unwrap_or("-"),
+                    record.indexed_at
+                ));
+            }
+        }
+
+        return description;
+    }
+}
+
+/// Current Unix time in seconds, for `IndexedPageRecord::indexed_at`.
+pub fn unix_timestamp() -> u64{
+    return SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or_default();
+}
+
+/// Parses a Notion timestamp such as `2022-07-06T20:25:00.000Z` into Unix seconds.
+/// Fractional seconds are dropped; anything but a UTC timestamp yields `None`.
+pub fn parse_notion_timestamp(timestamp: &str) -> Option<u64>{
+    let timestamp = timestamp.strip_suffix('Z')?;
+    let (date, time) = timestamp.split_once('T')?;
+    let time = time.split('.').next()?;
+
+    let date_parts: Vec<i64> = date.split('-').map(|x| x.parse().ok()).collect::<Option<Vec<i64>>>()?;
+    let time_parts: Vec<i64> = time.split(':').map(|x| x.parse().ok()).collect::<Option<Vec<i64>>>()?;
+    let (year, month, day, hour, minute, second) = match (date_parts.as_slice(), time_parts.as_slice()) {
+        (&[year, month, day], &[hour, minute, second]) => (year, month, day, hour, minute, second),
+        _ => return None
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || !(0..24).contains(&hour) || !(0..60).contains(&minute) || !(0..=60).contains(&second) {
+        return None;
+    }
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar, with years starting in
+    // March so the leap day comes last.
+    let shifted_year = if month <= 2 { year - 1 } else { year };
+    let era = shifted_year.div_euclid(400);
+    let year_of_era = shifted_year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days_since_epoch = era * 146097 + day_of_era - 719468;
+
+    return u64::try_from(days_since_epoch * 86400 + hour * 3600 + minute * 60 + second).ok();
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn notion_timestamps_are_parsed_as_utc(){
+        assert_eq!(parse_notion_timestamp("1970-01-01T00:00:00.000Z"), Some(0));
+        assert_eq!(parse_notion_timestamp("2022-07-06T20:25:00.000Z"), Some(1657139100));
+        assert_eq!(parse_notion_timestamp("2000-02-29T23:59:59Z"), Some(951868799));
+        assert_eq!(parse_notion_timestamp("2024-03-01T00:00:00.000Z"), Some(1709251200));
+
+        for timestamp in ["2022-07-06T20:25:00.000+02:00", "2022-07-06", "2022-13-06T20:25:00.000Z", "1969-12-31T23:59:59.000Z", ""]{
+            assert_eq!(parse_notion_timestamp(timestamp), None, "{:?}", timestamp);
+        }
+    }
+
+    /// A store path in a fresh temporary directory.
+    fn temporary_state_path(test_name: &str) -> PathBuf{
+        let state_dir = std::env::temp_dir().join(format!("notion-llm-{}-{}", test_name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&state_dir);
+        std::fs::create_dir_all(&state_dir).unwrap();
+        return state_dir.join("sync_state.json");
+    }
+
+    fn record(page_id: &str) -> IndexedPageRecord{
+        return IndexedPageRecord{
+            page_id: page_id.to_string(),
+            title: format!("Page {}", page_id),
+            embedding_model: "model".to_string(),
+            last_edited_time: Some("2022-07-06T20:25:00.000Z".to_string()),
+            content_hash: "hash".to_string(),
+            settings_hash: "settings".to_string(),
+            point_ids: vec![Uuid::from_u128(1), Uuid::from_u128(2)],
+            indexed_at: 1657139200,
+            fetched_at: 1657139190
+        };
+    }
+
+    #[test]
+    fn saved_state_is_read_back(){
+        let state_path = temporary_state_path("state-round-trip");
+
+        let mut sync_state = SyncStateStore::open(&state_path).unwrap();
+        assert!(sync_state.collection_names().is_empty());
+        sync_state.upsert("recipes", record("page-b"));
+        sync_state.upsert("recipes", record("page-a"));
+        sync_state.upsert("notes", record("page-c"));
+        sync_state.save().unwrap();
+
+        let sync_state = SyncStateStore::open(&state_path).unwrap();
+        assert_eq!(sync_state.collection_names(), vec!["notes", "recipes"]);
+        let page_ids: Vec<&str> = sync_state.pages("recipes").iter().map(|x| x.page_id.as_str()).collect();
+        assert_eq!(page_ids, vec!["page-a", "page-b"]);
+
+        let page_record = sync_state.get("recipes", "page-a").unwrap();
+        assert_eq!(page_record.point_ids, vec![Uuid::from_u128(1), Uuid::from_u128(2)]);
+        assert_eq!(page_record.fetched_at, 1657139190);
+        assert!(sync_state.get("notes", "page-a").is_none());
+
+        std::fs::remove_dir_all(state_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn other_state_versions_are_rejected(){
+        let state_path = temporary_state_path("state-version");
+        std::fs::write(&state_path, r#"{"version": 99, "collections": {}}"#).unwrap();
+
+        match SyncStateStore::open(&state_path) {
+            Err(SyncStateError::UnsupportedVersion{ found, expected, .. }) => assert_eq!((found, expected), (99, SYNC_STATE_VERSION)),
+            other => panic!("expected UnsupportedVersion, got {:?}", other)
+        }
+
+        std::fs::write(&state_path, "{\"version\": 1, \"collec").unwrap();
+        assert!(matches!(SyncStateStore::open(&state_path), Err(SyncStateError::Decode{ .. })));
+
+        std::fs::remove_dir_all(state_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn a_failed_save_leaves_the_previous_state_intact(){
+        let state_path = temporary_state_path("state-atomic-save");
+        let temporary_path = state_path.with_extension("json.tmp");
+
+        let mut sync_state = SyncStateStore::open(&state_path).unwrap();
+        sync_state.upsert("recipes", record("page-a"));
+        sync_state.save().unwrap();
+        assert!(!temporary_path.exists(), "the temporary file is renamed into place");
+        let saved_state = std::fs::read_to_string(&state_path).unwrap();
+
+        // A directory in the way of the temporary file makes the next write fail.
+        std::fs::create_dir(&temporary_path).unwrap();
+        sync_state.upsert("recipes", record("page-b"));
+        assert!(matches!(sync_state.save(), Err(SyncStateError::Io{ path, .. }) if path == temporary_path));
+        assert_eq!(std::fs::read_to_string(&state_path).unwrap(), saved_state);
+
+        std::fs::remove_dir_all(state_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn resets_forget_records_but_keep_the_file_usable(){
+        let state_path = temporary_state_path("state-reset");
+
+        let mut sync_state = SyncStateStore::open(&state_path).unwrap();
+        sync_state.upsert("recipes", record("page-a"));
+        sync_state.upsert("recipes", record("page-b"));
+        sync_state.upsert("notes", record("page-c"));
+
+        assert_eq!(sync_state.reset_collection("recipes"), 2);
+        assert_eq!(sync_state.reset_collection("recipes"), 0);
+        assert_eq!(sync_state.collection_names(), vec!["notes"]);
+
+        assert_eq!(sync_state.reset(), 1);
+        assert!(sync_state.collection_names().is_empty());
+        sync_state.save().unwrap();
+        assert!(SyncStateStore::open(&state_path).unwrap().pages("notes").is_empty());
+
+        std::fs::remove_dir_all(state_path.parent().unwrap()).unwrap();
+    }
+}
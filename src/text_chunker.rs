@@ -0,0 +1,487 @@
+//! Splitting page text into chunks small enough to embed.
+//!
+//! Every chunk remembers where it came from: its position among the page's chunks, the
+//! Markdown headings it sits under and its character offsets in the page text. Those are
+//! stored as payload so search results can point at the right part of a page.
+
+use qdrant_client::prelude::Payload;
+
+pub const DEFAULT_CHUNK_SIZE_TOKENS: usize = 256;
+pub const DEFAULT_CHUNK_OVERLAP_TOKENS: usize = 32;
+pub const DEFAULT_MAX_CHUNK_CHARS: usize = 1500;
+
+/// How page text is split into chunks.
+///
+/// Tokens are approximated by whitespace-separated words, which keeps chunking
+/// independent of the embedding model's tokenizer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkingStrategy{
+    /// Windows of `chunk_size` tokens, consecutive windows sharing `overlap` tokens.
+    FixedTokens{ chunk_size: usize, overlap: usize },
+    /// Whole sentences packed together up to `max_chars` characters.
+    Sentences{ max_chars: usize },
+    /// One chunk per Markdown heading section; sections longer than `max_chars` are
+    /// split further on sentence boundaries.
+    MarkdownHeadings{ max_chars: usize },
+    /// One chunk per block, i.e. per paragraph, list, code block or table.
+    PerBlock,
+    /// Chunks built from the Notion block tree by `block_chunker::chunk_block_tree`.
+    /// Plain text has no block tree, so `chunk_text` falls back to `MarkdownHeadings`.
+    BlockTree{ max_chars: usize }
+}
+
+impl Default for ChunkingStrategy{
+    fn default() -> Self{
+        return ChunkingStrategy::BlockTree{ max_chars: DEFAULT_MAX_CHUNK_CHARS };
+    }
+}
+
+/// A piece of a page's text, ready to be embedded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk{
+    /// Position of the chunk among the chunks of its page, starting at 0.
+    pub chunk_index: usize,
+    pub text: String,
+    /// Titles of the Markdown headings the chunk starts under, outermost first.
+    pub heading_path: Vec<String>,
+    /// Character (not byte) offset of the chunk's first character in the page text.
+    pub char_start: usize,
+    /// Character offset just past the chunk's last character.
+    pub char_end: usize,
+    /// IDs of the top-level Notion blocks the chunk was rendered from, if known.
+    pub block_ids: Vec<String>
+}
+
+impl TextChunk{
+    /// Adds `chunk_index`, `heading_path`, `char_start` and `char_end` to `payload`.
+    pub fn insert_into_payload(&self, payload: &mut Payload){
+        payload.insert("chunk_index", self.chunk_index as i64);
+        payload.insert("heading_path", self.heading_path.clone());
+        payload.insert("char_start", self.char_start as i64);
+        payload.insert("char_end", self.char_end as i64);
+        if !self.block_ids.is_empty() {
+            payload.insert("block_ids", self.block_ids.clone());
+        }
+    }
+
+    /// The text to embed: the chunk prefixed with its heading breadcrumb, so chunks deep
+    /// inside a section still carry what they are about.
+    pub fn text_with_breadcrumb(&self) -> String{
+        if self.heading_path.is_empty() {
+            return self.text.clone();
+        }
+        return format!("{}\n\n{}", self.heading_path.join(" > "), self.text);
+    }
+
+    pub fn to_payload(&self) -> Payload{
+        let mut payload = Payload::new();
+        self.insert_into_payload(&mut payload);
+        return payload;
+    }
+}
+
+/// Splits `text`, usually the Markdown of a page, into chunks according to `strategy`.
+/// Whitespace around chunks is dropped and empty chunks are skipped.
+pub fn chunk_text(text: &str, strategy: &ChunkingStrategy) -> Vec<TextChunk>{
+    let chunk_spans = match strategy {
+        ChunkingStrategy::FixedTokens{ chunk_size, overlap } => token_window_spans(text, (0, text.len()), *chunk_size, *overlap),
+        ChunkingStrategy::Sentences{ max_chars } => sentence_chunk_spans(text, (0, text.len()), *max_chars),
+        ChunkingStrategy::MarkdownHeadings{ max_chars } | ChunkingStrategy::BlockTree{ max_chars } => {
+            heading_section_spans(text).into_iter().flat_map(|section_span| {
+                match span_char_len(text, section_span) <= *max_chars {
+                    true => vec![section_span],
+                    false => sentence_chunk_spans(text, section_span, *max_chars)
+                }
+            }).collect()
+        },
+        ChunkingStrategy::PerBlock => block_spans(text)
+    };
+
+    let headings = markdown_headings(text);
+
+    let mut chunks: Vec<TextChunk> = Vec::new();
+    for chunk_span in chunk_spans{
+        let (start, end) = trim_span(text, chunk_span);
+        if start >= end {
+            continue;
+        }
+
+        let char_start = text[..start].chars().count();
+        chunks.push(TextChunk{
+            chunk_index: chunks.len(),
+            text: text[start..end].to_string(),
+            heading_path: heading_path_at(&headings, start),
+            char_start,
+            char_end: char_start + text[start..end].chars().count(),
+            block_ids: Vec::new()
+        });
+    }
+
+    return chunks;
+}
+
+/// A Markdown heading line, located by the byte offset of its first character.
+struct MarkdownHeading{
+    offset: usize,
+    level: usize,
+    title: String
+}
+
+/// Byte spans of every line of `text` (without the line break), together with whether
+/// the line lies inside a fenced code block or a `$$` equation block.
+fn line_spans(text: &str) -> Vec<(usize, usize, bool)>{
+    let mut lines: Vec<(usize, usize, bool)> = Vec::new();
+    let mut open_fence: Option<&str> = None;
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n'){
+        let line_text = line.trim_end_matches(['\n', '\r']);
+        let fence_marker = ["```", "$$"].into_iter().find(|x| line_text.trim_start().starts_with(x));
+
+        // Fence lines belong to the fenced block they open or close.
+        let in_fence = open_fence.is_some() || fence_marker.is_some();
+        match (open_fence, fence_marker) {
+            (None, Some(fence_marker)) => {
+                let is_single_line = fence_marker == "$$" && line_text.trim().len() > 2 && line_text.trim().ends_with("$$");
+                if !is_single_line {
+                    open_fence = Some(fence_marker);
+                }
+            }
+            (Some(open_marker), Some(fence_marker)) if open_marker == fence_marker => {
+                open_fence = None;
+            }
+            _ => {}
+        }
+
+        lines.push((offset, offset + line_text.len(), in_fence));
+        offset += line.len();
+    }
+
+    return lines;
+}
+
+fn markdown_headings(text: &str) -> Vec<MarkdownHeading>{
+    return line_spans(text).into_iter().filter(|x| !x.2).filter_map(|(start, end, _)| {
+        let line = &text[start..end];
+        let level = line.chars().take_while(|x| *x == '#').count();
+        let title = line[level..].strip_prefix(' ')?;
+
+        if level == 0 || level > 6 {
+            return None;
+        }
+        return Some(MarkdownHeading{ offset: start, level, title: title.trim().to_string() });
+    }).collect();
+}
+
+/// The titles of the headings enclosing byte offset `offset`, outermost first.
+fn heading_path_at(headings: &[MarkdownHeading], offset: usize) -> Vec<String>{
+    let mut heading_stack: Vec<&MarkdownHeading> = Vec::new();
+
+    for heading in headings.iter().take_while(|x| x.offset <= offset){
+        while heading_stack.last().is_some_and(|x| x.level >= heading.level) {
+            heading_stack.pop();
+        }
+        heading_stack.push(heading);
+    }
+
+    return heading_stack.into_iter().map(|x| x.title.clone()).collect();
+}
+
+/// One span per heading section, plus one for any text before the first heading.
+fn heading_section_spans(text: &str) -> Vec<(usize, usize)>{
+    let mut section_starts: Vec<usize> = vec![0];
+    section_starts.extend(markdown_headings(text).into_iter().map(|x| x.offset).filter(|x| *x > 0));
+
+    let mut section_ends: Vec<usize> = section_starts[1..].to_vec();
+    section_ends.push(text.len());
+
+    return section_starts.into_iter().zip(section_ends).collect();
+}
+
+/// Runs of non-blank lines; blank lines inside code and equation blocks do not end a
+/// block.
+fn block_spans(text: &str) -> Vec<(usize, usize)>{
+    let mut blocks: Vec<(usize, usize)> = Vec::new();
+    let mut current_block: Option<(usize, usize)> = None;
+
+    for (start, end, in_fence) in line_spans(text){
+        let is_blank = text[start..end].trim().is_empty() && !in_fence;
+
+        current_block = match (current_block, is_blank) {
+            (Some(block), true) => {
+                blocks.push(block);
+                None
+            }
+            (None, true) => None,
+            (Some((block_start, _)), false) => Some((block_start, end)),
+            (None, false) => Some((start, end))
+        };
+    }
+    blocks.extend(current_block);
+
+    return blocks;
+}
+
+/// Sentence spans within `span`. Code and equation blocks are kept whole, and every
+/// other line ends a sentence, so list items and table rows stand on their own.
+fn sentence_spans(text: &str, span: (usize, usize)) -> Vec<(usize, usize)>{
+    let mut sentences: Vec<(usize, usize)> = Vec::new();
+
+    for (block_start, block_end) in block_spans(&text[span.0..span.1]){
+        let block_start = span.0 + block_start;
+        let block_end = span.0 + block_end;
+        let block_text = &text[block_start..block_end];
+
+        if contains_fenced_block(block_text) {
+            sentences.push((block_start, block_end));
+            continue;
+        }
+
+        let mut sentence_start = block_start;
+        let mut previous_char: Option<char> = None;
+        for (char_idx, current_char) in block_text.char_indices(){
+            let offset = block_start + char_idx;
+            let ends_sentence = current_char == '\n' || (current_char.is_whitespace() && matches!(previous_char, Some('.' | '!' | '?')));
+            if ends_sentence && offset > sentence_start {
+                sentences.push((sentence_start, offset));
+                sentence_start = offset;
+            }
+            previous_char = Some(current_char);
+        }
+        sentences.push((sentence_start, block_end));
+    }
+
+    return sentences;
+}
+
+/// Whether any line of `text` lies inside a fenced code block or a `$$` equation block.
+fn contains_fenced_block(text: &str) -> bool{
+    return line_spans(text).iter().any(|x| x.2);
+}
+
+/// Packs consecutive sentences of `span` into chunks of at most `max_chars` characters.
+/// A sentence longer than that is split on word boundaries instead, unless it is a code
+/// or equation block, which becomes an oversized chunk of its own.
+fn sentence_chunk_spans(text: &str, span: (usize, usize), max_chars: usize) -> Vec<(usize, usize)>{
+    let max_chars = max_chars.max(1);
+    let mut chunks: Vec<(usize, usize)> = Vec::new();
+    let mut current_chunk: Option<(usize, usize)> = None;
+
+    for sentence in sentence_spans(text, span){
+        let sentence = trim_span(text, sentence);
+        if sentence.0 >= sentence.1 {
+            continue;
+        }
+
+        if let Some(chunk) = current_chunk {
+            if span_char_len(text, (chunk.0, sentence.1)) <= max_chars {
+                current_chunk = Some((chunk.0, sentence.1));
+                continue;
+            }
+            chunks.push(chunk);
+            current_chunk = None;
+        }
+
+        if span_char_len(text, sentence) <= max_chars {
+            current_chunk = Some(sentence);
+        }
+        else if contains_fenced_block(&text[sentence.0..sentence.1]) {
+            chunks.push(sentence);
+        }
+        else{
+            chunks.extend(word_packed_spans(text, sentence, max_chars));
+        }
+    }
+    chunks.extend(current_chunk);
+
+    return chunks;
+}
+
+/// Packs the words of `span` into spans of at most `max_chars` characters; a single
+/// word longer than that becomes a span of its own.
+fn word_packed_spans(text: &str, span: (usize, usize), max_chars: usize) -> Vec<(usize, usize)>{
+    let mut chunks: Vec<(usize, usize)> = Vec::new();
+    let mut current_chunk: Option<(usize, usize)> = None;
+
+    for word in word_spans(text, span){
+        current_chunk = match current_chunk {
+            Some((chunk_start, _)) if span_char_len(text, (chunk_start, word.1)) <= max_chars => Some((chunk_start, word.1)),
+            Some(chunk) => {
+                chunks.push(chunk);
+                Some(word)
+            }
+            None => Some(word)
+        };
+    }
+    chunks.extend(current_chunk);
+
+    return chunks;
+}
+
+/// Windows of `chunk_size` words over `span`, each starting `chunk_size - overlap`
+/// words after the previous one.
+fn token_window_spans(text: &str, span: (usize, usize), chunk_size: usize, overlap: usize) -> Vec<(usize, usize)>{
+    let chunk_size = chunk_size.max(1);
+    if overlap >= chunk_size {
+        log::warn!("Chunk overlap {} is not smaller than the chunk size {}, using {}", overlap, chunk_size, chunk_size - 1);
+    }
+    let step = chunk_size - overlap.min(chunk_size - 1);
+
+    let words = word_spans(text, span);
+    let mut windows: Vec<(usize, usize)> = Vec::new();
+    let mut window_start = 0;
+
+    while window_start < words.len() {
+        let window_end = (window_start + chunk_size).min(words.len());
+        windows.push((words[window_start].0, words[window_end - 1].1));
+
+        if window_end == words.len() {
+            break;
+        }
+        window_start += step;
+    }
+
+    return windows;
+}
+
+fn word_spans(text: &str, span: (usize, usize)) -> Vec<(usize, usize)>{
+    let mut words: Vec<(usize, usize)> = Vec::new();
+    let mut word_start: Option<usize> = None;
+
+    for (char_idx, current_char) in text[span.0..span.1].char_indices(){
+        let offset = span.0 + char_idx;
+        match (word_start, current_char.is_whitespace()) {
+            (Some(start), true) => {
+                words.push((start, offset));
+                word_start = None;
+            }
+            (None, false) => {
+                word_start = Some(offset);
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = word_start {
+        words.push((start, span.1));
+    }
+
+    return words;
+}
+
+fn trim_span(text: &str, span: (usize, usize)) -> (usize, usize){
+    let span_text = &text[span.0..span.1];
+    let start = span.0 + (span_text.len() - span_text.trim_start().len());
+    let end = span.0 + span_text.trim_end().len();
+    return (start, end.max(start));
+}
+
+fn span_char_len(text: &str, span: (usize, usize)) -> usize{
+    return text[span.0..span.1].chars().count();
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn chunk_texts(chunks: &[TextChunk]) -> Vec<&str>{
+        return chunks.iter().map(|x| x.text.as_str()).collect();
+    }
+
+    /// Every chunk's offsets must point at its own text in the page, in characters.
+    fn assert_offsets_match(text: &str, chunks: &[TextChunk]){
+        let text_chars: Vec<char> = text.chars().collect();
+        for chunk in chunks{
+            let chunk_text: String = text_chars[chunk.char_start..chunk.char_end].iter().collect();
+            assert_eq!(chunk_text, chunk.text);
+        }
+    }
+
+    #[test]
+    fn oversized_code_block_is_kept_whole(){
+        let text = "# Code\n\n```rust\nfn a() {}\n\nfn b() {}\n```\n\nAfter the code.";
+        let chunks = chunk_text(text, &ChunkingStrategy::MarkdownHeadings{ max_chars: 30 });
+
+        assert_eq!(chunk_texts(&chunks), vec!["# Code", "```rust\nfn a() {}\n\nfn b() {}\n```", "After the code."]);
+        assert_offsets_match(text, &chunks);
+    }
+
+    #[test]
+    fn oversized_equation_block_is_kept_whole(){
+        let text = "Intro.\n\n$$\na^2 + b^2 = c^2 + d^2 + e^2\n$$";
+        let chunks = chunk_text(text, &ChunkingStrategy::Sentences{ max_chars: 10 });
+
+        assert_eq!(chunk_texts(&chunks), vec!["Intro.", "$$\na^2 + b^2 = c^2 + d^2 + e^2\n$$"]);
+    }
+
+    #[test]
+    fn long_sentences_are_split_on_words(){
+        let text = "one two three four five six";
+        let chunks = chunk_text(text, &ChunkingStrategy::Sentences{ max_chars: 10 });
+
+        assert_eq!(chunk_texts(&chunks), vec!["one two", "three four", "five six"]);
+        assert_offsets_match(text, &chunks);
+    }
+
+    #[test]
+    fn sentences_are_packed_up_to_max_chars(){
+        let text = "First one. Second one. Third one.";
+        let chunks = chunk_text(text, &ChunkingStrategy::Sentences{ max_chars: 22 });
+
+        assert_eq!(chunk_texts(&chunks), vec!["First one. Second one.", "Third one."]);
+        assert_offsets_match(text, &chunks);
+    }
+
+    #[test]
+    fn offsets_count_characters_not_bytes(){
+        let text = "# Café\n\nCrème brûlée é.\n\n## Süß\n\nÜber alles.";
+        let chunks = chunk_text(text, &ChunkingStrategy::MarkdownHeadings{ max_chars: 1000 });
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].char_start, text[..text.find("## Süß").unwrap()].chars().count());
+        assert_offsets_match(text, &chunks);
+    }
+
+    #[test]
+    fn chunks_carry_their_heading_path(){
+        let text = "Preface\n\n# Recipes\n\n## Pasta\n\nBoil water.\n\n# Notes\n\nNone.";
+        let chunks = chunk_text(text, &ChunkingStrategy::PerBlock);
+
+        let heading_paths: Vec<Vec<String>> = chunks.iter().map(|x| x.heading_path.clone()).collect();
+        assert_eq!(heading_paths, vec![
+            vec![],
+            vec!["Recipes".to_string()],
+            vec!["Recipes".to_string(), "Pasta".to_string()],
+            vec!["Recipes".to_string(), "Pasta".to_string()],
+            vec!["Notes".to_string()],
+            vec!["Notes".to_string()]
+        ]);
+        assert_eq!(chunks.iter().map(|x| x.chunk_index).collect::<Vec<usize>>(), (0..6).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn headings_inside_code_blocks_are_ignored(){
+        let text = "# Shell\n\n```sh\n# not a heading\necho hi\n```";
+        let chunks = chunk_text(text, &ChunkingStrategy::MarkdownHeadings{ max_chars: 1000 });
+
+        assert_eq!(chunk_texts(&chunks), vec![text]);
+        assert_eq!(chunks[0].heading_path, vec!["Shell".to_string()]);
+    }
+
+    #[test]
+    fn per_block_keeps_blank_lines_inside_code_blocks(){
+        let text = "Intro\n\n```\na\n\nb\n```\n\nOutro";
+        let chunks = chunk_text(text, &ChunkingStrategy::PerBlock);
+
+        assert_eq!(chunk_texts(&chunks), vec!["Intro", "```\na\n\nb\n```", "Outro"]);
+        assert_offsets_match(text, &chunks);
+    }
+
+    #[test]
+    fn token_windows_overlap(){
+        let text = "a b c d e f g";
+        let chunks = chunk_text(text, &ChunkingStrategy::FixedTokens{ chunk_size: 4, overlap: 1 });
+
+        assert_eq!(chunk_texts(&chunks), vec!["a b c d", "d e f g"]);
+        assert_offsets_match(text, &chunks);
+    }
+}